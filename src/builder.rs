@@ -0,0 +1,271 @@
+use crate::{builtins::isolation::Isolation, error::*, JsEngine, JsEngineBuilder};
+use js::{Context, Tokio};
+use snafu::ResultExt;
+
+const DEFAULT_MEMORY_LIMIT: usize = 2 * 1024 * 1024;
+const DEFAULT_MAX_STACK_SIZE: usize = 256 * 1024;
+
+impl Default for JsEngineBuilder {
+    fn default() -> Self {
+        Self {
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            max_stack_size: DEFAULT_MAX_STACK_SIZE,
+            gc_threshold: None,
+            json: Default::default(),
+            module_resolver: None,
+            isolate_globals: false,
+            reset_globals: false,
+            freeze_builtins: false,
+            #[cfg(feature = "console")]
+            console: true,
+            #[cfg(feature = "console")]
+            console_sink: None,
+            #[cfg(feature = "fetch")]
+            fetch: true,
+            #[cfg(feature = "timers")]
+            timers: true,
+            #[cfg(feature = "fetch")]
+            fetch_policy: crate::FetchPolicy::default(),
+            #[cfg(feature = "fetch")]
+            fetch_backend: None,
+            #[cfg(feature = "fetch")]
+            fetch_client: crate::FetchClientConfig::default(),
+            #[cfg(feature = "fetch")]
+            reqwest_backend: Default::default(),
+            #[cfg(feature = "dispatcher")]
+            sender: None,
+            #[cfg(feature = "builtin_processor")]
+            processors: None,
+        }
+    }
+}
+
+impl JsEngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Max amount of memory the runtime could use. 0 means unlimited.
+    pub fn memory_limit(mut self, limit: usize) -> Self {
+        self.memory_limit = limit;
+        self
+    }
+
+    /// Max size of the stack the runtime could use.
+    pub fn max_stack_size(mut self, size: usize) -> Self {
+        self.max_stack_size = size;
+        self
+    }
+
+    /// Memory threshold to trigger the garbage collection.
+    pub fn gc_threshold(mut self, threshold: usize) -> Self {
+        self.gc_threshold = Some(threshold);
+        self
+    }
+
+    /// How the requests and results of the runs are converted between json and javascript
+    /// values, e.g. [`JsonOptions::lossless`](crate::JsonOptions::lossless) to keep large
+    /// integers, dates and binary data. By default unsupported values become `null` or `{}`.
+    pub fn json_options(mut self, options: crate::JsonOptions) -> Self {
+        self.json = options;
+        self
+    }
+
+    /// Load the modules the scripts `import` with the resolver, e.g.
+    /// `import { sign } from "lib/crypto"` at the beginning of the code given to
+    /// [`JsEngine::run`](crate::JsEngine::run). Without a resolver, importing fails.
+    pub fn module_resolver(mut self, resolver: impl crate::ModuleResolver) -> Self {
+        self.module_resolver = Some(crate::module::SharedResolver(std::sync::Arc::new(resolver)));
+        self
+    }
+
+    /// Restore the own properties of the global object after each run to what they are when the
+    /// engine is built, so that the globals a script adds, replaces or removes do not leak into
+    /// the following runs. The changes made by the host, e.g.
+    /// [`JsEngine::load_global_js`](crate::JsEngine::load_global_js) or
+    /// [`JsEngine::set_global`](crate::JsEngine::set_global), are kept. When runs overlap, the
+    /// globals are restored once the last one ends. Disabled by default.
+    ///
+    /// Only the top-level bindings are restored: changes inside the global values, e.g.
+    /// `JSON.parse = f` or `Array.prototype.map = f`, still leak, use
+    /// [`JsEngineBuilder::freeze_builtins`] to prevent them for the builtins. Neither could the
+    /// globals a script makes non-configurable, e.g. with
+    /// `Object.defineProperty(globalThis, "x", { value: 1 })`, be removed or restored: they
+    /// are kept, and reported as a warning.
+    pub fn isolate_globals(mut self, enabled: bool) -> Self {
+        self.isolate_globals = enabled;
+        self
+    }
+
+    /// record the globals as the host sets them up, so that the pool could restore them when
+    /// the engine is returned, see [`JsEngine::reset_globals`]
+    pub(crate) fn reset_globals(mut self, enabled: bool) -> Self {
+        self.reset_globals = enabled;
+        self
+    }
+
+    /// Make the builtin globals, e.g. `console`, `fetch` or `JSON`, read-only and freeze them,
+    /// so that scripts could not monkey-patch them. This includes `dispatcher` and the
+    /// namespaces of the exposed processors, e.g. `auth`. The prototypes, e.g.
+    /// `Array.prototype`, are left alone. Disabled by default.
+    pub fn freeze_builtins(mut self, enabled: bool) -> Self {
+        self.freeze_builtins = enabled;
+        self
+    }
+
+    /// Install the `console` global. Enabled by default.
+    #[cfg(feature = "console")]
+    pub fn console(mut self, enabled: bool) -> Self {
+        self.console = enabled;
+        self
+    }
+
+    /// Write the `console` output of the scripts to the sink, e.g. a
+    /// [`MemorySink`](crate::MemorySink) in tests. By default the output is printed to stdout
+    /// if it is a terminal, otherwise it is emitted as `tracing` events.
+    #[cfg(feature = "console")]
+    pub fn console_sink(mut self, sink: impl crate::ConsoleSink) -> Self {
+        self.console_sink = Some(crate::builtins::SharedSink(std::sync::Arc::new(sink)));
+        self
+    }
+
+    /// Install the `fetch` global. Enabled by default.
+    #[cfg(feature = "fetch")]
+    pub fn fetch(mut self, enabled: bool) -> Self {
+        self.fetch = enabled;
+        self
+    }
+
+    /// Install the `setTimeout`, `setInterval`, `clearTimeout`, `clearInterval` and
+    /// `queueMicrotask` globals. Enabled by default. The pending timers are cancelled when the
+    /// last of the overlapping runs finishes, and waiting for them counts toward the deadline
+    /// of the run.
+    #[cfg(feature = "timers")]
+    pub fn timers(mut self, enabled: bool) -> Self {
+        self.timers = enabled;
+        self
+    }
+
+    /// Restrict the urls, sizes and number of the requests sent by `fetch`. By default any
+    /// http(s) url could be fetched.
+    #[cfg(feature = "fetch")]
+    pub fn fetch_policy(mut self, policy: crate::FetchPolicy) -> Self {
+        self.fetch_policy = policy;
+        self.reqwest_backend = Default::default();
+        self
+    }
+
+    /// Configure the reqwest client used by `fetch`. The client is created once and shared by
+    /// all the engines built from this builder and its clones.
+    #[cfg(feature = "fetch")]
+    pub fn fetch_client(mut self, config: crate::FetchClientConfig) -> Self {
+        self.fetch_client = config;
+        self.reqwest_backend = Default::default();
+        self
+    }
+
+    /// Send the requests of `fetch` with the backend instead of reqwest, e.g. a
+    /// [`MockFetchBackend`](crate::MockFetchBackend) in tests.
+    #[cfg(feature = "fetch")]
+    pub fn fetch_backend(mut self, backend: impl crate::FetchBackend) -> Self {
+        self.fetch_backend = Some(crate::builtins::SharedBackend(std::sync::Arc::new(backend)));
+        self
+    }
+
+    /// Install the `dispatcher` global, which sends the messages to the given sender.
+    #[cfg(feature = "dispatcher")]
+    pub fn dispatcher(mut self, sender: flume::Sender<crate::MsgChannel>) -> Self {
+        self.sender = Some(sender);
+        self
+    }
+
+    /// Install the `dispatcher` global, and process the messages with the processors in the
+    /// registry. The registry could still be updated after the engine is built.
+    #[cfg(feature = "builtin_processor")]
+    pub fn processors(mut self, registry: crate::ProcessorRegistry) -> Self {
+        self.processors = Some(registry);
+        self
+    }
+
+    pub fn build(self) -> Result<JsEngine> {
+        let rt = js::Runtime::new().context(JsRuntimeSnafu)?;
+        rt.set_max_stack_size(self.max_stack_size);
+        rt.set_memory_limit(self.memory_limit);
+        if let Some(threshold) = self.gc_threshold {
+            rt.set_gc_threshold(threshold);
+        }
+        if let Some(resolver) = &self.module_resolver {
+            resolver.install(&rt);
+        }
+
+        let ctx = Context::full(&rt).context(JsContextSnafu)?;
+        rt.spawn_executor(Tokio);
+        #[cfg(feature = "fetch")]
+        let fetcher = self.fetcher()?;
+        // created last, so that nothing could fail before the engine releases it when dropped
+        let restore = self.isolate_globals || self.reset_globals;
+        let isolation = match restore || self.freeze_builtins {
+            true => {
+                let isolation = ctx.with(|ctx| Isolation::new(ctx, restore, self.isolate_globals));
+                Some(isolation.context(JsExecuteSnafu)?)
+            }
+            false => None,
+        };
+
+        let engine = JsEngine {
+            runtime: rt,
+            context: ctx,
+            json: self.json,
+            #[cfg(feature = "dispatcher")]
+            sender: self.dispatcher_sender(),
+            #[cfg(feature = "builtin_processor")]
+            processors: self.processors.clone(),
+            #[cfg(feature = "builtin_processor")]
+            expose: Default::default(),
+            #[cfg(feature = "fetch")]
+            fetcher,
+            #[cfg(feature = "console")]
+            console: self.console.then(|| {
+                let sink = self.console_sink.clone();
+                let sink = sink.unwrap_or_else(crate::builtins::SharedSink::detect);
+                std::sync::Arc::new(crate::builtins::Console::new(sink))
+            }),
+            #[cfg(feature = "timers")]
+            timers: self.timers.then(Default::default),
+            isolation,
+            runs: Default::default(),
+            started: Default::default(),
+        };
+        engine.init_globals(&self)?;
+        #[cfg(feature = "builtin_processor")]
+        engine.expose_processors()?;
+        Ok(engine)
+    }
+
+    #[cfg(feature = "fetch")]
+    fn fetcher(&self) -> Result<Option<std::sync::Arc<crate::builtins::Fetcher>>> {
+        if !self.fetch {
+            return Ok(None);
+        }
+        let backend = match &self.fetch_backend {
+            Some(backend) => backend.clone(),
+            None => self
+                .reqwest_backend
+                .get_or_build(&self.fetch_policy, &self.fetch_client)
+                .context(FetchClientSnafu)?,
+        };
+        let fetcher = crate::builtins::Fetcher::new(self.fetch_policy.clone(), backend);
+        Ok(Some(std::sync::Arc::new(fetcher)))
+    }
+
+    #[cfg(feature = "dispatcher")]
+    fn dispatcher_sender(&self) -> Option<flume::Sender<crate::MsgChannel>> {
+        #[cfg(feature = "builtin_processor")]
+        if let (None, Some(registry)) = (&self.sender, &self.processors) {
+            let (tx, rx) = flume::unbounded::<crate::MsgChannel>();
+            crate::engine::run_processors(rx, registry.clone());
+            return Some(tx);
+        }
+        self.sender.clone()
+    }
+}
@@ -0,0 +1,169 @@
+// creates the `console` of each run on top of its native `write`, and installs the global one
+(function (native) {
+  delete globalThis.__console;
+
+  function display(value) {
+    if (typeof value === "string") {
+      return `'${value}'`;
+    }
+    if (value !== null && typeof value === "object") {
+      return JSON.stringify(value);
+    }
+    return String(value);
+  }
+
+  // render the rows of `data` as a table like node does, e.g.
+  // ┌─────────┬─────┐
+  // │ (index) │  a  │
+  // ├─────────┼─────┤
+  // │    0    │  1  │
+  // └─────────┴─────┘
+  function renderTable(data, columns) {
+    const rows = Object.entries(data);
+    const isObject = (value) => value !== null && typeof value === "object";
+    let keys = columns ? [...columns] : [];
+    if (!columns) {
+      for (const [, value] of rows) {
+        for (const key of isObject(value) ? Object.keys(value) : []) {
+          if (!keys.includes(key)) {
+            keys.push(key);
+          }
+        }
+      }
+    }
+    const hasValues = rows.some(([, value]) => !isObject(value));
+    const header = ["(index)", ...keys, ...(hasValues ? ["Values"] : [])];
+    const body = rows.map(([index, value]) => [
+      index,
+      ...keys.map((key) => (isObject(value) && key in value ? display(value[key]) : "")),
+      ...(hasValues ? [isObject(value) ? "" : display(value)] : []),
+    ]);
+    const widths = header.map((_, i) =>
+      Math.max(...[header, ...body].map((row) => String(row[i]).length)) + 2,
+    );
+    const center = (text, width) => {
+      const left = Math.floor((width - text.length) / 2);
+      return " ".repeat(left) + text + " ".repeat(width - text.length - left);
+    };
+    const line = (l, m, r) => l + widths.map((w) => "─".repeat(w)).join(m) + r;
+    const row = (cells) => "│" + cells.map((c, i) => center(String(c), widths[i])).join("│") + "│";
+    return [
+      line("┌", "┬", "┐"),
+      row(header),
+      line("├", "┼", "┤"),
+      ...body.map(row),
+      line("└", "┴", "┘"),
+    ].join("\n");
+  }
+
+  // each run has its own counters, timers and groups
+  function create(nativeWrite) {
+    const timers = new Map();
+    const counts = new Map();
+    let depth = 0;
+
+    function write(level, args, message) {
+      if (message === undefined) {
+        nativeWrite(level, depth, args);
+      } else {
+        nativeWrite(level, depth, args, message);
+      }
+    }
+
+    function elapsed(label) {
+      const start = timers.get(label);
+      if (start === undefined) {
+        write("warn", [`Timer '${label}' does not exist`]);
+        return undefined;
+      }
+      return `${label}: ${Date.now() - start}ms`;
+    }
+
+    const console = {
+      log(...args) {
+        write("log", args);
+      },
+      info(...args) {
+        write("info", args);
+      },
+      debug(...args) {
+        write("debug", args);
+      },
+      trace(...args) {
+        const stack = new Error().stack.split("\n").slice(1).join("\n");
+        const message = ["Trace:", ...args.map((arg) => (typeof arg === "string" ? arg : display(arg)))];
+        write("trace", args, `${message.join(" ")}\n${stack}`.trimEnd());
+      },
+      warn(...args) {
+        write("warn", args);
+      },
+      error(...args) {
+        write("error", args);
+      },
+      dir(value) {
+        write("log", [value]);
+      },
+      assert(condition, ...args) {
+        if (condition) {
+          return;
+        }
+        if (typeof args[0] === "string") {
+          args[0] = `Assertion failed: ${args[0]}`;
+        } else {
+          args.unshift("Assertion failed");
+        }
+        write("error", args);
+      },
+      count(label = "default") {
+        const count = (counts.get(label) ?? 0) + 1;
+        counts.set(label, count);
+        write("log", [`${label}: ${count}`]);
+      },
+      countReset(label = "default") {
+        counts.delete(label);
+      },
+      time(label = "default") {
+        if (timers.has(label)) {
+          write("warn", [`Timer '${label}' already exists`]);
+          return;
+        }
+        timers.set(label, Date.now());
+      },
+      timeLog(label = "default", ...args) {
+        const message = elapsed(label);
+        if (message !== undefined) {
+          write("log", [message, ...args]);
+        }
+      },
+      timeEnd(label = "default") {
+        const message = elapsed(label);
+        if (message !== undefined) {
+          timers.delete(label);
+          write("log", [message]);
+        }
+      },
+      group(...labels) {
+        if (labels.length > 0) {
+          write("log", labels);
+        }
+        depth += 1;
+      },
+      groupEnd() {
+        depth = Math.max(0, depth - 1);
+      },
+      table(data, columns) {
+        if (data === null || typeof data !== "object") {
+          write("log", [data]);
+          return;
+        }
+        write("log", [data], renderTable(data, columns));
+      },
+    };
+    console.groupCollapsed = console.group;
+    console.dirxml = console.log;
+    return console;
+  }
+
+  globalThis.console = create(native.write);
+  return create;
+})(globalThis.__console);
@@ -0,0 +1,305 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::{sync::lock, JsonOptions, JsonValue, LogEntry, LogLevel};
+use js::{Ctx, Func, Function, IntoJs, Object, Opt, Persistent};
+use serde_json::Value;
+
+mod sink;
+
+pub(crate) use sink::SharedSink;
+pub use sink::{ConsoleSink, MemorySink, StdoutSink, TracingSink};
+
+/// js implementation of `console` on top of `__console`
+const CONSOLE_JS: &str = include_str!("console.js");
+
+/// Writes the console output of an engine to its sink, and captures it while runs ask for it.
+#[derive(Debug)]
+pub(crate) struct Console {
+    sink: SharedSink,
+    runs: Mutex<Runs>,
+    /// the `create(write)` function of `console.js`, which creates the `console` of a run
+    create: Mutex<Option<Persistent<Function<'static>>>>,
+}
+
+/// the runs in progress, by id. The id 0 is the global `console`, used outside of the runs,
+/// e.g. by the modules and the global helpers.
+#[derive(Debug, Default)]
+struct Runs {
+    last: u32,
+    active: HashMap<u32, Run>,
+}
+
+#[derive(Debug)]
+struct Run {
+    /// name of the script, passed to the sink with the output of the run
+    script: String,
+    /// the output of the run, if it is captured
+    captured: Option<Vec<LogEntry>>,
+}
+
+impl Console {
+    pub(crate) fn new(sink: SharedSink) -> Self {
+        Self {
+            sink,
+            runs: Default::default(),
+            create: Default::default(),
+        }
+    }
+
+    /// install the global `console`, and keep the function creating the `console` of the runs
+    pub(crate) fn init(self: &Arc<Self>, ctx: Ctx<'_>) -> Result<(), js::Error> {
+        let native = Object::new(ctx)?;
+        native.set("write", self.writer(ctx, 0, None)?)?;
+        ctx.globals().set("__console", native)?;
+        let create: Function = ctx.eval(CONSOLE_JS)?;
+        *lock(&self.create) = Some(Persistent::save(ctx, create));
+        Ok(())
+    }
+
+    /// release the persistent function, which must be done while holding the runtime lock
+    pub(crate) fn release(&self, ctx: Ctx<'_>) {
+        if let Some(create) = lock(&self.create).take() {
+            drop(create.restore(ctx));
+        }
+    }
+
+    /// start a run of the named script, whose output is written by the `console` created for
+    /// its id
+    pub(crate) fn begin_run(&self, script: &str) -> u32 {
+        let mut runs = lock(&self.runs);
+        runs.last = runs.last.checked_add(1).unwrap_or(1);
+        let id = runs.last;
+        let run = Run {
+            script: script.to_owned(),
+            captured: None,
+        };
+        runs.active.insert(id, run);
+        id
+    }
+
+    /// end the run, its output is no longer captured
+    pub(crate) fn end_run(&self, id: u32) {
+        lock(&self.runs).active.remove(&id);
+    }
+
+    /// capture the output of the run, until it is taken by [`Console::captured`]
+    pub(crate) fn capture(&self, id: u32) {
+        if let Some(run) = lock(&self.runs).active.get_mut(&id) {
+            run.captured = Some(Vec::new());
+        }
+    }
+
+    /// take the output captured for the run so far
+    pub(crate) fn captured(&self, id: u32) -> Vec<LogEntry> {
+        lock(&self.runs)
+            .active
+            .get_mut(&id)
+            .and_then(|run| run.captured.take())
+            .unwrap_or_default()
+    }
+
+    /// create the `console` of the run, passed to the script as the `console` argument
+    pub(crate) fn create<'js>(
+        self: &Arc<Self>,
+        ctx: Ctx<'js>,
+        id: u32,
+    ) -> Result<js::Value<'js>, js::Error> {
+        let create = match &*lock(&self.create) {
+            Some(create) => create.clone().restore(ctx)?,
+            None => return ctx.globals().get("console"),
+        };
+        let script = lock(&self.runs)
+            .active
+            .get(&id)
+            .map(|run| run.script.clone());
+        create.call((self.writer(ctx, id, script)?,))
+    }
+
+    /// the native `write(level, depth, args, message?)` of the run, `message` is given if
+    /// `console.js` formats the arguments itself, e.g. `console.table`. The output is written
+    /// with the name of the script, even after the run ends, e.g. by a pending callback.
+    fn writer<'js>(
+        self: &Arc<Self>,
+        ctx: Ctx<'js>,
+        id: u32,
+        script: Option<String>,
+    ) -> Result<js::Value<'js>, js::Error> {
+        let console = self.clone();
+        let write = move |ctx: Ctx<'js>,
+                          level: String,
+                          depth: u32,
+                          args: js::Value<'js>,
+                          message: Opt<String>|
+              -> Result<(), js::Error> {
+            // logging an object which refers to itself should not fail
+            let options = JsonOptions::new().circular_marker(true);
+            let args = match JsonValue::from_js_with(ctx, args, &options)?.0 {
+                Value::Array(args) => args.into_iter().map(JsonValue).collect(),
+                arg => vec![JsonValue(arg)],
+            };
+            let message = message.0.unwrap_or_else(|| format_message(&args));
+            let indent = "  ".repeat(depth as usize);
+            let message = match indent.is_empty() {
+                true => message,
+                false => message
+                    .lines()
+                    .map(|line| format!("{}{}", indent, line))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            };
+            console.write(id, script.as_deref(), parse_level(&level), message, args);
+            Ok(())
+        };
+        Func::new("write", write).into_js(ctx)
+    }
+
+    /// write the entry of the run to the sink, and to the buffer of the run if it is captured.
+    /// The output of the global `console` belongs to the run in progress, if there is only one.
+    fn write(
+        &self,
+        id: u32,
+        script: Option<&str>,
+        level: LogLevel,
+        message: String,
+        args: Vec<JsonValue>,
+    ) {
+        let entry = LogEntry {
+            level,
+            timestamp: SystemTime::now(),
+            message,
+            args,
+        };
+        let script = {
+            let mut runs = lock(&self.runs);
+            let run = match id {
+                0 if runs.active.len() == 1 => runs.active.values_mut().next(),
+                id => runs.active.get_mut(&id),
+            };
+            let name = match (script, &run) {
+                (Some(script), _) => script.to_owned(),
+                (None, Some(run)) => run.script.clone(),
+                (None, None) => "script".to_owned(),
+            };
+            if let Some(captured) = run.and_then(|run| run.captured.as_mut()) {
+                captured.push(entry.clone());
+            }
+            name
+        };
+        self.sink.0.write(&script, &entry);
+    }
+}
+
+fn parse_level(level: &str) -> LogLevel {
+    match level {
+        "trace" => LogLevel::Trace,
+        "debug" => LogLevel::Debug,
+        "info" => LogLevel::Info,
+        "warn" => LogLevel::Warn,
+        "error" => LogLevel::Error,
+        _ => LogLevel::Log,
+    }
+}
+
+/// Apply the `%s`, `%d`, `%i`, `%f`, `%o`, `%O`, `%j` and `%c` substitutions of the first
+/// argument if it is a string, then append the rest of the arguments separated by spaces.
+fn format_message(args: &[JsonValue]) -> String {
+    let (mut message, mut rest) = match args.split_first() {
+        Some((JsonValue(Value::String(format)), rest)) if format.contains('%') => {
+            let mut rest = rest.iter();
+            let mut message = String::with_capacity(format.len());
+            let mut chars = format.chars().peekable();
+            while let Some(c) = chars.next() {
+                let spec = match (c, chars.peek()) {
+                    ('%', Some(&spec)) if "sdifoOjc%".contains(spec) => spec,
+                    _ => {
+                        message.push(c);
+                        continue;
+                    }
+                };
+                chars.next();
+                if spec == '%' {
+                    message.push('%');
+                    continue;
+                }
+                match rest.next() {
+                    Some(arg) => message.push_str(&substitute(spec, arg)),
+                    // not enough arguments, keep the placeholder as is
+                    None => {
+                        message.push('%');
+                        message.push(spec);
+                    }
+                }
+            }
+            (message, rest.collect::<Vec<_>>())
+        }
+        _ => (String::new(), args.iter().collect()),
+    };
+    if message.is_empty() {
+        return rest
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+    }
+    for arg in rest.drain(..) {
+        message.push(' ');
+        message.push_str(&arg.to_string());
+    }
+    message
+}
+
+fn substitute(spec: char, arg: &JsonValue) -> String {
+    let number = || match &arg.0 {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        Value::Bool(b) => Some(f64::from(u8::from(*b))),
+        _ => None,
+    };
+    match spec {
+        'd' | 'i' | 'f' => match number() {
+            Some(n) if spec == 'i' => format!("{}", n.trunc()),
+            Some(n) => format!("{}", n),
+            None => "NaN".to_owned(),
+        },
+        'o' | 'j' => serde_json::to_string(&arg.0).unwrap_or_default(),
+        'O' => serde_json::to_string_pretty(&arg.0).unwrap_or_default(),
+        // css styles are meaningless outside of the browser
+        'c' => String::new(),
+        _ => arg.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn console_message_should_be_formatted() {
+        let format = |args: Value| {
+            let args: Vec<_> = match args {
+                Value::Array(args) => args.into_iter().map(JsonValue).collect(),
+                _ => unreachable!(),
+            };
+            format_message(&args)
+        };
+        assert_eq!(format(json!(["a", 1, [1, 2]])), "a 1 [1, 2]");
+        assert_eq!(
+            format(json!(["%s is %d years", "bob", 42])),
+            "bob is 42 years"
+        );
+        assert_eq!(format(json!(["%i%% %f", 4.7, "1.5"])), "4% 1.5");
+        assert_eq!(format(json!(["%d", "x"])), "NaN");
+        assert_eq!(
+            format(json!(["%o %c!", { "a": [1] }, "color: red"])),
+            r#"{"a":[1]} !"#
+        );
+        assert_eq!(format(json!(["%s and %s", "a"])), "a and %s");
+        assert_eq!(format(json!(["100%", "done"])), "100% done");
+        assert_eq!(format(json!([1, "%s", 2])), "1 %s 2");
+    }
+}
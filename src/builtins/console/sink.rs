@@ -0,0 +1,100 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{sync::lock, LogEntry, LogLevel};
+
+/// Receives what the scripts of an engine write to `console`, e.g. to forward it to a log
+/// pipeline. `script` is the name of the script which wrote the entry, i.e. `script` for
+/// [`JsEngine::run`](crate::JsEngine::run) or the name given to
+/// [`JsEngine::compile`](crate::JsEngine::compile).
+pub trait ConsoleSink: Send + Sync + 'static {
+    fn write(&self, script: &str, entry: &LogEntry);
+}
+
+/// Prints the messages to stdout.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutSink;
+
+/// Emits the messages as `tracing` events, with the script name in the `script` field.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingSink;
+
+/// Keeps the messages in memory, e.g. to inspect them in tests. Clones share the messages.
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    entries: Arc<Mutex<Vec<(String, LogEntry)>>>,
+}
+
+impl ConsoleSink for StdoutSink {
+    fn write(&self, _script: &str, entry: &LogEntry) {
+        println!("{}", entry.message);
+    }
+}
+
+impl ConsoleSink for TracingSink {
+    fn write(&self, script: &str, entry: &LogEntry) {
+        let message = &entry.message;
+        match entry.level {
+            LogLevel::Trace => trace!(script, "{}", message),
+            LogLevel::Debug => debug!(script, "{}", message),
+            LogLevel::Info | LogLevel::Log => info!(script, "{}", message),
+            LogLevel::Warn => warn!(script, "{}", message),
+            LogLevel::Error => error!(script, "{}", message),
+        }
+    }
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The script names and messages received so far, in order.
+    pub fn entries(&self) -> Vec<(String, LogEntry)> {
+        lock(&self.entries).clone()
+    }
+
+    /// Remove and return the messages received so far.
+    pub fn take(&self) -> Vec<(String, LogEntry)> {
+        std::mem::take(&mut *lock(&self.entries))
+    }
+}
+
+impl ConsoleSink for MemorySink {
+    fn write(&self, script: &str, entry: &LogEntry) {
+        lock(&self.entries).push((script.to_owned(), entry.clone()));
+    }
+}
+
+impl fmt::Debug for MemorySink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemorySink")
+            .field("entries", &lock(&self.entries).len())
+            .finish()
+    }
+}
+
+/// A [`ConsoleSink`] shared by the engines built from the same builder.
+#[derive(Clone)]
+pub(crate) struct SharedSink(pub(crate) Arc<dyn ConsoleSink>);
+
+impl SharedSink {
+    /// print to stdout if it is a terminal, otherwise emit `tracing` events
+    pub(crate) fn detect() -> Self {
+        if atty::is(atty::Stream::Stdout) {
+            Self(Arc::new(StdoutSink))
+        } else {
+            Self(Arc::new(TracingSink))
+        }
+    }
+}
+
+impl fmt::Debug for SharedSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedSink").finish()
+    }
+}
@@ -0,0 +1,81 @@
+// the helpers on top of the native `dispatcher`. Returns the `expose(processors)` function,
+// which is kept by the host, so that scripts could not expose processors themselves.
+// `dispatcher` is always read from the global object: rquickjs 0.1.7 frees its native object
+// twice when the runtime is dropped if a closure captures it.
+(function () {
+  class DispatchError extends Error {
+    constructor(namespace, method, error) {
+      super(error.message);
+      this.name = "DispatchError";
+      this.namespace = namespace;
+      this.method = method;
+      this.code = error.code;
+      this.details = error.details;
+    }
+  }
+
+  globalThis.DispatchError = DispatchError;
+
+  dispatcher.dispatch = async function (namespace, method, args) {
+    const res = await this.send(namespace, method, args);
+    if ("error" in res) {
+      throw new DispatchError(namespace, method, res.error);
+    }
+    return res.ok;
+  };
+
+  // the exposed processors and their namespaces, e.g. `auth` for `auth.create_token(args)`. They
+  // are kept here rather than on `dispatcher`, so that it could be frozen with the other builtins.
+  let processors = {};
+  const namespaces = new Map();
+  // the namespaces whose globals are defined once the builtins are frozen
+  const frozenGlobals = new Set();
+
+  dispatcher.namespaces = function () {
+    return Object.keys(processors);
+  };
+
+  dispatcher.methods = function (namespace) {
+    return processors[namespace] ?? [];
+  };
+
+  // expose the processors. Returns the namespaces `skipped` as they would shadow an existing
+  // global such as `console`, and the globals `changed` by the host.
+  return function expose(exposed) {
+    // once the builtins are frozen, so are the namespaces, and their globals read them from the
+    // map as the globals could not be replaced anymore. A removed namespace is then `undefined`.
+    const frozen = Object.isFrozen(globalThis.dispatcher);
+    const previous = new Set(namespaces.keys());
+    const skipped = [];
+    namespaces.clear();
+    processors = {};
+    for (const [namespace, methods] of Object.entries(exposed)) {
+      if (!previous.has(namespace) && !frozenGlobals.has(namespace) && namespace in globalThis) {
+        skipped.push(namespace);
+        continue;
+      }
+      const obj = {};
+      for (const method of methods) {
+        obj[method] = (args) => globalThis.dispatcher.dispatch(namespace, method, args);
+      }
+      if (!frozen) {
+        globalThis[namespace] = obj;
+      } else if (!frozenGlobals.has(namespace)) {
+        frozenGlobals.add(namespace);
+        Object.defineProperty(globalThis, namespace, {
+          get: () => namespaces.get(namespace),
+          enumerable: true,
+          configurable: false,
+        });
+      }
+      namespaces.set(namespace, frozen ? Object.freeze(obj) : obj);
+      processors[namespace] = methods;
+    }
+    for (const namespace of previous) {
+      if (!namespaces.has(namespace) && !frozenGlobals.has(namespace)) {
+        delete globalThis[namespace];
+      }
+    }
+    return { skipped, changed: [...previous, ...namespaces.keys()] };
+  };
+})();
@@ -2,11 +2,17 @@ mod msg_channel;
 
 pub use msg_channel::MsgChannel;
 
+/// js helpers built on top of the native dispatcher, e.g. `dispatch` and `DispatchError`
+pub(crate) const DISPATCHER_JS: &str = include_str!("dispatcher.js");
+
 #[js::bind(object, public)]
 #[quickjs(bare)]
 #[allow(non_upper_case_globals)]
 pub(crate) mod disp {
-    use crate::{JsonValue, MsgChannel};
+    use crate::{JsonValue, MsgChannel, ProcessorError};
+    use js::Promised;
+    use serde_json::json;
+    use std::future::Future;
     use tracing::{info, warn};
 
     #[derive(Debug, Clone)]
@@ -22,26 +28,40 @@ pub(crate) mod disp {
             Self { sender }
         }
 
-        pub fn dispatch(
+        /// Send the message to the processor, returns a promise resolved with either
+        /// `{ ok: value }` or `{ error: { code, message, details } }`. Scripts should use
+        /// `dispatch` defined in `dispatcher.js` instead, which throws a `DispatchError`.
+        pub fn send(
             &self,
             ns: String,
             name: String,
             args: JsonValue,
-        ) -> Result<JsonValue, js::Error> {
+        ) -> Promised<impl Future<Output = JsonValue>> {
             info!("dispatch: {} {} {:?}", ns, name, args);
-            let (msg, res) = MsgChannel::new(ns, name, args);
-            self.sender
-                .send(msg)
-                .map_err(|_| js::Error::UnrelatedRuntime)?;
-            res.recv()
-                .map_err(|e| {
-                    warn!("recv error: {:?}", e);
-                    js::Error::UnrelatedRuntime
-                })?
-                .map_err(|e| {
-                    warn!("execution error: {:?}", e);
-                    js::Error::Unknown
-                })
+            let sender = self.sender.clone();
+            Promised(async move {
+                let ret = match do_send(sender, ns, name, args).await {
+                    Ok(v) => json!({ "ok": v }),
+                    Err(e) => {
+                        warn!("execution error: {:?}", e);
+                        json!({ "error": e })
+                    }
+                };
+                JsonValue(ret)
+            })
         }
     }
+
+    async fn do_send(
+        sender: flume::Sender<MsgChannel>,
+        ns: String,
+        name: String,
+        args: JsonValue,
+    ) -> Result<JsonValue, ProcessorError> {
+        let unavailable =
+            || ProcessorError::new("dispatcher is unavailable").with_code("unavailable");
+        let (msg, res) = MsgChannel::new(ns, name, args);
+        sender.send_async(msg).await.map_err(|_| unavailable())?;
+        res.recv_async().await.map_err(|_| unavailable())?
+    }
 }
@@ -1,4 +1,4 @@
-use crate::JsonValue;
+use crate::{JsonValue, ProcessorError};
 
 #[derive(Debug)]
 pub struct MsgChannel {
@@ -9,7 +9,7 @@ pub struct MsgChannel {
     /// args for the calling function
     pub args: JsonValue,
     /// the sender of the response
-    pub res: flume::Sender<Result<JsonValue, String>>,
+    pub res: flume::Sender<Result<JsonValue, ProcessorError>>,
 }
 
 impl MsgChannel {
@@ -17,7 +17,7 @@ impl MsgChannel {
         namespace: impl Into<String>,
         name: impl Into<String>,
         args: JsonValue,
-    ) -> (Self, flume::Receiver<Result<JsonValue, String>>) {
+    ) -> (Self, flume::Receiver<Result<JsonValue, ProcessorError>>) {
         let (sender, receiver) = flume::bounded(1);
         (
             Self {
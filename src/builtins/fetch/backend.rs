@@ -0,0 +1,207 @@
+use std::{error::Error as StdError, fmt, time::Duration};
+
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+
+use super::FetchPolicy;
+
+/// Sends the http requests issued by `fetch`. The engines use reqwest by default, implement
+/// this trait to route the requests through another client, or use [`MockFetchBackend`] in
+/// tests.
+///
+/// The urls are checked against the [`FetchPolicy`] before they reach the backend, but a custom
+/// backend is responsible to block the private addresses after dns resolution and redirects.
+///
+/// [`MockFetchBackend`]: crate::MockFetchBackend
+#[async_trait]
+pub trait FetchBackend: Send + Sync + 'static {
+    async fn fetch(&self, req: FetchRequest) -> Result<FetchResponse, FetchError>;
+}
+
+/// A request issued by `fetch`, the query params are already appended to the url.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchRequest {
+    pub url: String,
+    /// upper case method, e.g. `GET`
+    pub method: String,
+    /// header names are in lower case
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+    /// timeout of the request, capped by the [`FetchPolicy`]
+    pub timeout: Option<Duration>,
+}
+
+/// The response of a [`FetchRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchResponse {
+    /// the final url after redirects
+    pub url: String,
+    pub status: u16,
+    /// defaults to the canonical reason of the status if empty
+    pub status_text: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Error returned by a [`FetchBackend`]. The `fetch` promise is rejected with its message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchError {
+    pub message: String,
+}
+
+impl FetchRequest {
+    /// value of the header, the name is case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+impl FetchResponse {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            url: String::new(),
+            status,
+            status_text: String::new(),
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    /// A response with the json body and the `content-type` header.
+    pub fn json(status: u16, body: &serde_json::Value) -> Self {
+        Self::new(status, body.to_string()).with_header("content-type", "application/json")
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// value of the header, the name is case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+impl FetchError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl From<String> for FetchError {
+    fn from(message: String) -> Self {
+        Self::new(message)
+    }
+}
+
+impl From<&str> for FetchError {
+    fn from(message: &str) -> Self {
+        Self::new(message)
+    }
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(e: reqwest::Error) -> Self {
+        // reqwest hides the cause, e.g. a dns error, in the source chain
+        let mut message = e.to_string();
+        let mut source = e.source();
+        while let Some(e) = source {
+            message = format!("{}: {}", message, e);
+            source = e.source();
+        }
+        Self::new(message)
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl StdError for FetchError {}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// The default backend, which sends the requests with a shared reqwest client.
+#[derive(Debug, Clone)]
+pub(crate) struct ReqwestBackend {
+    client: Client,
+    policy: FetchPolicy,
+}
+
+impl ReqwestBackend {
+    /// the client should be built with the same policy, see `FetchClientConfig::build`
+    pub(crate) fn new(client: Client, policy: FetchPolicy) -> Self {
+        Self { client, policy }
+    }
+}
+
+#[async_trait]
+impl FetchBackend for ReqwestBackend {
+    async fn fetch(&self, req: FetchRequest) -> Result<FetchResponse, FetchError> {
+        let policy = &self.policy;
+        let method = req
+            .method
+            .parse()
+            .map_err(|_| FetchError::new(format!("invalid method: {}", req.method)))?;
+        let mut builder = self.client.request(method, &req.url);
+        for (k, v) in &req.headers {
+            builder = builder.header(k, v);
+        }
+        if let Some(timeout) = req.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(body) = req.body {
+            builder = builder.body(body);
+        }
+
+        let mut res = builder.send().await?;
+        let status = res.status();
+        let headers = res
+            .headers()
+            .iter()
+            .map(|(k, v)| {
+                let v = String::from_utf8_lossy(v.as_bytes()).into_owned();
+                (k.as_str().to_owned(), v)
+            })
+            .collect();
+        let url = res.url().to_string();
+        // stop reading once the body is too large, instead of buffering all of it
+        let mut body = Vec::new();
+        while let Some(chunk) = res.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if let Some(max) = policy
+                .max_response_size_limit()
+                .filter(|max| body.len() > *max)
+            {
+                let message = format!("response of {} is larger than {} bytes", url, max);
+                return Err(FetchError::new(message));
+            }
+        }
+        Ok(FetchResponse {
+            url,
+            status: status.as_u16(),
+            status_text: String::new(),
+            headers,
+            body,
+        })
+    }
+}
+
+/// the canonical reason of the status, e.g. `Not Found`
+pub(crate) fn status_text(status: u16) -> String {
+    StatusCode::from_u16(status)
+        .ok()
+        .and_then(|status| status.canonical_reason())
+        .unwrap_or_default()
+        .to_owned()
+}
@@ -0,0 +1,180 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Certificate, Client, Proxy,
+};
+
+use super::{backend::ReqwestBackend, FetchError, FetchPolicy, SharedBackend};
+use crate::sync::lock;
+
+/// Configuration of the reqwest client used by `fetch`. The client is created once and shared
+/// by all the engines built from the same [`JsEngineBuilder`], including the engines of a
+/// [`JsEnginePool`], so that they reuse the connections and TLS sessions.
+///
+/// [`JsEngineBuilder`]: crate::JsEngineBuilder
+/// [`JsEnginePool`]: crate::JsEnginePool
+#[derive(Debug, Clone, Default)]
+pub struct FetchClientConfig {
+    user_agent: Option<String>,
+    default_headers: Vec<(String, String)>,
+    proxy: Option<String>,
+    root_certificates: Vec<Vec<u8>>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    pool_idle_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+}
+
+impl FetchClientConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `user-agent` header sent with every request.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// A header sent with every request, unless the script sets it.
+    pub fn default_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.default_headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Send all the requests through the proxy, e.g. `http://proxy.internal:3128`. Without it,
+    /// the proxy of the `HTTP_PROXY` / `HTTPS_PROXY` environment variables is used, unless
+    /// [`FetchPolicy::block_private_ips`](crate::FetchPolicy::block_private_ips) is enabled.
+    ///
+    /// The proxy resolves the hosts itself, so with `block_private_ips` only the hosts which
+    /// are ip addresses are checked, not the addresses the host names resolve to. The proxy
+    /// should refuse the private addresses then.
+    pub fn proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    /// Trust the PEM encoded root certificate in addition to the builtin ones.
+    pub fn root_certificate(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.root_certificates.push(pem.into());
+        self
+    }
+
+    /// Default timeout of the requests, from connecting until the body is read.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout of the connect phase of the requests.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// How long idle connections are kept alive in the pool.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Max number of idle connections kept per host.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// build the client, enforcing the redirect and private address rules of the policy
+    pub(crate) fn build(&self, policy: &FetchPolicy) -> Result<Client, FetchError> {
+        let mut builder = Client::builder().redirect(policy.redirect_policy());
+        if let Some(resolver) = policy.resolver() {
+            builder = builder.dns_resolver(Arc::new(resolver));
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.default_headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| FetchError::new(format!("invalid header name: {}", name)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| FetchError::new(format!("invalid value of header {}", name)))?;
+            headers.append(name, value);
+        }
+        builder = builder.default_headers(headers);
+        match &self.proxy {
+            Some(proxy) => builder = builder.proxy(Proxy::all(proxy)?),
+            // a proxy from `HTTP(S)_PROXY` would resolve the hosts itself, out of the reach of
+            // the private address check of the resolver
+            None if policy.resolver().is_some() => builder = builder.no_proxy(),
+            None => {}
+        }
+        for pem in &self.root_certificates {
+            builder = builder.add_root_certificate(Certificate::from_pem(pem)?);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// The reqwest backend created on first use, shared by the clones of a builder.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LazyBackend(Arc<Mutex<Option<SharedBackend>>>);
+
+impl LazyBackend {
+    pub(crate) fn get_or_build(
+        &self,
+        policy: &FetchPolicy,
+        config: &FetchClientConfig,
+    ) -> Result<SharedBackend, FetchError> {
+        let mut backend = lock(&self.0);
+        if let Some(backend) = &*backend {
+            return Ok(backend.clone());
+        }
+        let client = config.build(policy)?;
+        let shared = SharedBackend(Arc::new(ReqwestBackend::new(client, policy.clone())));
+        *backend = Some(shared.clone());
+        Ok(shared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, JsEngine};
+
+    #[test]
+    fn reqwest_backend_should_be_built_once() {
+        let lazy = LazyBackend::default();
+        let (policy, config) = (FetchPolicy::new(), FetchClientConfig::new());
+        let a = lazy.get_or_build(&policy, &config).expect("valid");
+        let b = lazy.clone().get_or_build(&policy, &config).expect("valid");
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+
+        let config = FetchClientConfig::new().default_header("bad header", "x");
+        let ret = LazyBackend::default().get_or_build(&policy, &config);
+        assert!(matches!(ret, Err(e) if e.message.contains("invalid header name")));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn invalid_fetch_client_should_fail_the_build() {
+        let config = FetchClientConfig::new().proxy("not a proxy url");
+        let ret = JsEngine::builder().fetch_client(config).build();
+        assert!(matches!(ret, Err(Error::FetchClient { .. })));
+    }
+}
@@ -0,0 +1,198 @@
+(function (http) {
+  const bodySymbol = Symbol("body");
+
+  // normalize the body into a string, an ArrayBuffer or a Uint8Array view
+  function toBody(body, headers) {
+    if (body === undefined || body === null) {
+      return null;
+    }
+    if (typeof body === "string" || body instanceof ArrayBuffer) {
+      return body;
+    }
+    if (ArrayBuffer.isView(body)) {
+      return new Uint8Array(body.buffer, body.byteOffset, body.byteLength);
+    }
+    // plain objects are sent as json
+    if (!headers.has("content-type")) {
+      headers.set("content-type", "application/json");
+    }
+    return JSON.stringify(body);
+  }
+
+  class Headers {
+    #map = new Map();
+
+    constructor(init) {
+      if (init instanceof Headers) {
+        init.forEach((value, name) => this.append(name, value));
+      } else if (Array.isArray(init)) {
+        for (const [name, value] of init) {
+          this.append(name, value);
+        }
+      } else if (init) {
+        for (const [name, value] of Object.entries(init)) {
+          this.append(name, value);
+        }
+      }
+    }
+
+    append(name, value) {
+      const key = String(name).toLowerCase();
+      const prev = this.#map.get(key);
+      this.#map.set(key, prev === undefined ? String(value) : `${prev}, ${value}`);
+    }
+
+    delete(name) {
+      this.#map.delete(String(name).toLowerCase());
+    }
+
+    get(name) {
+      const value = this.#map.get(String(name).toLowerCase());
+      return value === undefined ? null : value;
+    }
+
+    has(name) {
+      return this.#map.has(String(name).toLowerCase());
+    }
+
+    set(name, value) {
+      this.#map.set(String(name).toLowerCase(), String(value));
+    }
+
+    forEach(callback, thisArg) {
+      for (const [name, value] of this.entries()) {
+        callback.call(thisArg, value, name, this);
+      }
+    }
+
+    *entries() {
+      const names = [...this.#map.keys()].sort();
+      for (const name of names) {
+        yield [name, this.#map.get(name)];
+      }
+    }
+
+    *keys() {
+      for (const [name] of this.entries()) {
+        yield name;
+      }
+    }
+
+    *values() {
+      for (const [, value] of this.entries()) {
+        yield value;
+      }
+    }
+
+    [Symbol.iterator]() {
+      return this.entries();
+    }
+
+    toJSON() {
+      return Object.fromEntries(this.entries());
+    }
+  }
+
+  class Body {
+    constructor(body) {
+      this[bodySymbol] = body;
+      this.bodyUsed = false;
+    }
+
+    #consume() {
+      if (this.bodyUsed) {
+        throw new TypeError("body has already been consumed");
+      }
+      this.bodyUsed = true;
+      return this[bodySymbol];
+    }
+
+    async text() {
+      const body = this.#consume();
+      if (body === null) {
+        return "";
+      }
+      return typeof body === "string" ? body : http.decode(body);
+    }
+
+    async json() {
+      return JSON.parse(await this.text());
+    }
+
+    async arrayBuffer() {
+      const body = this.#consume();
+      if (body === null) {
+        return new ArrayBuffer(0);
+      }
+      if (typeof body === "string") {
+        return http.encode(body);
+      }
+      if (body instanceof ArrayBuffer) {
+        return body;
+      }
+      return body.buffer.slice(body.byteOffset, body.byteOffset + body.byteLength);
+    }
+  }
+
+  class Request extends Body {
+    constructor(input, init = {}) {
+      const source = typeof input === "string" ? { url: input } : input;
+      const headers = new Headers(init.headers ?? source.headers);
+      // the body of a request is kept under the symbol, not as `body`
+      const body = source instanceof Request ? source[bodySymbol] : source.body;
+      super(toBody(init.body ?? body, headers));
+      this.url = String(source.url);
+      this.method = String(init.method ?? source.method ?? "GET").toUpperCase();
+      this.headers = headers;
+      // extensions: query params appended to the url, and timeout in milliseconds
+      this.params = init.params ?? source.params ?? {};
+      this.timeout = init.timeout ?? source.timeout;
+    }
+
+    clone() {
+      return new Request(this, { body: this[bodySymbol] });
+    }
+  }
+
+  class Response extends Body {
+    constructor(body, init = {}) {
+      const headers = new Headers(init.headers);
+      super(toBody(body, headers));
+      this.status = init.status ?? 200;
+      this.statusText = init.statusText ?? "";
+      this.headers = headers;
+      this.url = init.url ?? "";
+      this.ok = this.status >= 200 && this.status < 300;
+      this.type = "basic";
+    }
+
+    clone() {
+      return new Response(this[bodySymbol], this);
+    }
+  }
+
+  async function fetch(input, init) {
+    const req = new Request(input, init);
+    const params = Array.isArray(req.params) ? req.params : Object.entries(req.params);
+    const res = await http.send(
+      {
+        url: req.url,
+        method: req.method,
+        headers: [...req.headers.entries()],
+        params: params.map(([k, v]) => [String(k), String(v)]),
+        timeout: req.timeout,
+      },
+      req[bodySymbol],
+    );
+    return new Response(res.body, {
+      status: res.status,
+      statusText: res.statusText,
+      headers: res.headers,
+      url: res.url,
+    });
+  }
+
+  Object.assign(globalThis, { fetch, Headers, Request, Response });
+})(globalThis.__http);
+
+delete globalThis.__http;
@@ -0,0 +1,102 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+
+use super::{FetchBackend, FetchError, FetchRequest, FetchResponse};
+use crate::sync::lock;
+
+/// A [`FetchBackend`] answering the requests from a route table of canned responses, and
+/// recording the requests it receives. Unmatched requests are answered with `404`.
+///
+/// ```
+/// # use easy_qjs::{FetchResponse, JsEngine, MockFetchBackend};
+/// let mock = MockFetchBackend::new()
+///     .route("GET", "https://api.example.com/users", FetchResponse::new(200, "[]"))
+///     .route("*", "https://api.example.com/health", FetchResponse::new(204, ""));
+/// let builder = JsEngine::builder().fetch_backend(mock.clone());
+/// ```
+#[derive(Clone, Default)]
+pub struct MockFetchBackend {
+    inner: Arc<Mutex<MockState>>,
+}
+
+#[derive(Default)]
+struct MockState {
+    routes: Vec<Route>,
+    requests: Vec<FetchRequest>,
+}
+
+struct Route {
+    method: String,
+    url: String,
+    response: FetchResponse,
+}
+
+impl MockFetchBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer the requests with the given method (`*` for any) and url with the response. The
+    /// url matches with or without the query string of the request. Earlier routes win.
+    pub fn route(self, method: &str, url: impl Into<String>, response: FetchResponse) -> Self {
+        lock(&self.inner).routes.push(Route {
+            method: method.to_ascii_uppercase(),
+            url: url.into(),
+            response,
+        });
+        self
+    }
+
+    /// The requests received so far, in order.
+    pub fn requests(&self) -> Vec<FetchRequest> {
+        lock(&self.inner).requests.clone()
+    }
+}
+
+impl Route {
+    fn matches(&self, req: &FetchRequest) -> bool {
+        let path = req
+            .url
+            .split_once('?')
+            .map_or(req.url.as_str(), |(path, _)| path);
+        (self.method == "*" || self.method == req.method)
+            && (self.url == req.url || self.url == path)
+    }
+}
+
+#[async_trait]
+impl FetchBackend for MockFetchBackend {
+    async fn fetch(&self, req: FetchRequest) -> Result<FetchResponse, FetchError> {
+        let mut state = lock(&self.inner);
+        let response = state
+            .routes
+            .iter()
+            .find(|route| route.matches(&req))
+            .map(|route| route.response.clone());
+        let mut response = response.unwrap_or_else(|| FetchResponse::new(404, "no route"));
+        if response.url.is_empty() {
+            response.url = req.url.clone();
+        }
+        state.requests.push(req);
+        Ok(response)
+    }
+}
+
+impl fmt::Debug for MockFetchBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = lock(&self.inner);
+        let routes: Vec<_> = state
+            .routes
+            .iter()
+            .map(|route| format!("{} {}", route.method, route.url))
+            .collect();
+        f.debug_struct("MockFetchBackend")
+            .field("routes", &routes)
+            .field("requests", &state.requests.len())
+            .finish()
+    }
+}
@@ -0,0 +1,192 @@
+mod backend;
+mod client;
+mod mock;
+mod policy;
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::JsonValue;
+use anyhow::{bail, Context};
+use js::{ArrayBuffer, Async, Ctx, FromJs, Func, IntoJs, Object, TypedArray};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+
+pub(crate) use client::LazyBackend;
+
+pub use backend::{FetchBackend, FetchError, FetchRequest, FetchResponse};
+pub use client::FetchClientConfig;
+pub use mock::MockFetchBackend;
+pub use policy::FetchPolicy;
+
+/// js implementation of `fetch`, `Headers`, `Request` and `Response` on top of `__http`
+pub(crate) const FETCH_JS: &str = include_str!("fetch.js");
+
+/// Raw bytes of a request or response body.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Bytes(pub(crate) Vec<u8>);
+
+/// The request sent by `fetch`, normalized by `fetch.js`.
+#[derive(Debug, Clone, Deserialize)]
+struct ScriptRequest {
+    url: String,
+    #[serde(default = "default_method")]
+    method: String,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    params: Vec<(String, String)>,
+    /// timeout in milliseconds
+    timeout: Option<u64>,
+}
+
+/// A [`FetchBackend`] shared by the engines built from the same builder.
+#[derive(Clone)]
+pub(crate) struct SharedBackend(pub(crate) Arc<dyn FetchBackend>);
+
+/// Create the `__http` object, the native side of `fetch` captured by `fetch.js`.
+pub(crate) fn http_object<'js>(
+    ctx: Ctx<'js>,
+    fetcher: Arc<Fetcher>,
+) -> Result<Object<'js>, js::Error> {
+    let http = Object::new(ctx)?;
+    let send = move |req: JsonValue, body: Option<Bytes>| {
+        let fetcher = fetcher.clone();
+        async move {
+            let req: ScriptRequest = serde_json::from_value(req.into())
+                .map_err(|e| js::Error::new_from_js_message("object", "Request", e.to_string()))?;
+            fetcher
+                .fetch(req, body)
+                .await
+                .map_err(|e| js::Error::Io(std::io::Error::other(format!("{:#}", e))))
+        }
+    };
+    http.set("send", Func::new("send", Async(send)))?;
+    // decode the utf-8 body into string
+    let decode = |body: Bytes| String::from_utf8_lossy(&body.0).into_owned();
+    http.set("decode", Func::new("decode", decode))?;
+    // encode the string into utf-8 body
+    let encode = |s: String| Bytes(s.into_bytes());
+    http.set("encode", Func::new("encode", encode))?;
+    Ok(http)
+}
+
+/// Sends the requests of an engine to its backend according to its [`FetchPolicy`].
+#[derive(Debug)]
+pub(crate) struct Fetcher {
+    policy: FetchPolicy,
+    backend: SharedBackend,
+    /// requests sent since the current run started
+    requests: AtomicUsize,
+}
+
+fn default_method() -> String {
+    "GET".to_owned()
+}
+
+impl Fetcher {
+    pub(crate) fn new(policy: FetchPolicy, backend: SharedBackend) -> Self {
+        Self {
+            policy,
+            backend,
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    /// reset the request counter when a new run starts
+    pub(crate) fn reset(&self) {
+        self.requests.store(0, Ordering::Relaxed);
+    }
+
+    async fn fetch(
+        &self,
+        req: ScriptRequest,
+        body: Option<Bytes>,
+    ) -> anyhow::Result<FetchResponse> {
+        let policy = &self.policy;
+        let sent = self.requests.fetch_add(1, Ordering::Relaxed);
+        if let Some(max) = policy.max_requests_limit().filter(|max| sent >= *max) {
+            bail!("more than {} requests in a single run", max);
+        }
+        let mut url = Url::parse(&req.url).with_context(|| format!("invalid url: {}", req.url))?;
+        policy.check_url(&url)?;
+        if !req.params.is_empty() {
+            url.query_pairs_mut().extend_pairs(&req.params);
+        }
+
+        let req = FetchRequest {
+            url: url.into(),
+            method: req.method.to_uppercase(),
+            headers: req
+                .headers
+                .into_iter()
+                .map(|(k, v)| (k.to_ascii_lowercase(), v))
+                .collect(),
+            body: body.map(|body| body.0),
+            timeout: policy.request_timeout(req.timeout.map(Duration::from_millis)),
+        };
+        let mut res = self.backend.0.fetch(req).await?;
+        if let Some(max) = policy
+            .max_response_size_limit()
+            .filter(|max| res.body.len() > *max)
+        {
+            bail!("response of {} is larger than {} bytes", res.url, max);
+        }
+        if res.status_text.is_empty() {
+            res.status_text = backend::status_text(res.status);
+        }
+        Ok(res)
+    }
+}
+
+impl fmt::Debug for SharedBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedBackend").finish()
+    }
+}
+
+impl<'js> FromJs<'js> for Bytes {
+    fn from_js(ctx: Ctx<'js>, val: js::Value<'js>) -> Result<Self, js::Error> {
+        if val.is_string() {
+            return Ok(Self(String::from_js(ctx, val)?.into_bytes()));
+        }
+        let obj = Object::from_js(ctx, val)?;
+        if let Ok(arr) = TypedArray::<u8>::from_object(obj.clone()) {
+            let bytes: &[u8] = arr.as_ref();
+            return Ok(Self(bytes.to_vec()));
+        }
+        let buf = ArrayBuffer::from_object(obj)?;
+        let bytes: &[u8] = buf.as_ref();
+        Ok(Self(bytes.to_vec()))
+    }
+}
+
+impl<'js> IntoJs<'js> for Bytes {
+    fn into_js(self, ctx: Ctx<'js>) -> Result<js::Value<'js>, js::Error> {
+        ArrayBuffer::new(ctx, self.0)?.into_js(ctx)
+    }
+}
+
+impl<'js> IntoJs<'js> for FetchResponse {
+    fn into_js(self, ctx: Ctx<'js>) -> Result<js::Value<'js>, js::Error> {
+        let obj = Object::new(ctx)?;
+        obj.set("url", self.url)?;
+        obj.set("status", self.status)?;
+        obj.set("statusText", self.status_text)?;
+        let headers: Vec<Value> = self
+            .headers
+            .into_iter()
+            .map(|(k, v)| Value::Array(vec![k.into(), v.into()]))
+            .collect();
+        obj.set("headers", JsonValue(headers.into()))?;
+        obj.set("body", Bytes(self.body))?;
+        obj.into_js(ctx)
+    }
+}
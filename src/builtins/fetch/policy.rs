@@ -0,0 +1,328 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use hyper::client::connect::dns::Name;
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    redirect, Url,
+};
+
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Restrictions applied to every `fetch` issued by the scripts of an engine.
+///
+/// The default policy allows any http(s) url, which is fine for trusted scripts. For untrusted
+/// scripts, restrict the hosts and block the private addresses, e.g.:
+///
+/// ```
+/// # use std::time::Duration;
+/// # use easy_qjs::FetchPolicy;
+/// let policy = FetchPolicy::new()
+///     .allowed_schemes(["https"])
+///     .allow_host("api.example.com")
+///     .allow_host("*.cdn.example.com")
+///     .block_private_ips(true)
+///     .max_response_size(1024 * 1024)
+///     .timeout(Duration::from_secs(5))
+///     .max_requests(10);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FetchPolicy {
+    schemes: Vec<String>,
+    hosts: Option<Vec<String>>,
+    block_private_ips: bool,
+    max_response_size: Option<usize>,
+    max_redirects: usize,
+    timeout: Option<Duration>,
+    max_requests: Option<usize>,
+}
+
+impl Default for FetchPolicy {
+    fn default() -> Self {
+        Self {
+            schemes: vec!["http".to_owned(), "https".to_owned()],
+            hosts: None,
+            block_private_ips: false,
+            max_response_size: None,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            timeout: None,
+            max_requests: None,
+        }
+    }
+}
+
+impl FetchPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Url schemes allowed to be fetched. Defaults to `http` and `https`.
+    pub fn allowed_schemes<S: Into<String>>(
+        mut self,
+        schemes: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.schemes = schemes
+            .into_iter()
+            .map(|s| s.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Allow the host matching the pattern, which is either an exact host name (`example.com`),
+    /// a wildcard for its subdomains (`*.example.com`) or `*` for any host. Any host is allowed
+    /// if no pattern is added.
+    pub fn allow_host(mut self, pattern: impl Into<String>) -> Self {
+        self.hosts
+            .get_or_insert_with(Vec::new)
+            .push(pattern.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Reject the urls whose host is, or resolves to, a loopback, private, link-local or
+    /// otherwise non-public address. Disabled by default. When enabled, the proxy of the
+    /// environment is not used, and an explicit
+    /// [`FetchClientConfig::proxy`](crate::FetchClientConfig::proxy) only gets the hosts which
+    /// are ip addresses checked.
+    pub fn block_private_ips(mut self, enabled: bool) -> Self {
+        self.block_private_ips = enabled;
+        self
+    }
+
+    /// Max size of the response body in bytes.
+    pub fn max_response_size(mut self, bytes: usize) -> Self {
+        self.max_response_size = Some(bytes);
+        self
+    }
+
+    /// Max number of redirects to follow, defaults to 10. With 0 the redirect response itself
+    /// is returned to the script.
+    pub fn max_redirects(mut self, redirects: usize) -> Self {
+        self.max_redirects = redirects;
+        self
+    }
+
+    /// Timeout of a single request. Scripts could only ask for a shorter one.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Max number of requests a single run of a script could issue.
+    pub fn max_requests(mut self, requests: usize) -> Self {
+        self.max_requests = Some(requests);
+        self
+    }
+
+    pub(crate) fn max_response_size_limit(&self) -> Option<usize> {
+        self.max_response_size
+    }
+
+    pub(crate) fn max_requests_limit(&self) -> Option<usize> {
+        self.max_requests
+    }
+
+    /// the timeout of a request, given the one asked by the script
+    pub(crate) fn request_timeout(&self, requested: Option<Duration>) -> Option<Duration> {
+        match (requested, self.timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// check the scheme and host of the url. Host names are checked again after resolution.
+    pub(crate) fn check_url(&self, url: &Url) -> anyhow::Result<()> {
+        if !self.schemes.iter().any(|s| s == url.scheme()) {
+            bail!("scheme of {} is not allowed", url);
+        }
+        let host = url
+            .host_str()
+            .with_context(|| format!("{} has no host", url))?
+            .to_ascii_lowercase();
+        if let Some(hosts) = &self.hosts {
+            if !hosts.iter().any(|pattern| host_matches(pattern, &host)) {
+                bail!("host of {} is not allowed", url);
+            }
+        }
+        if self.block_private_ips {
+            // host names are checked by the resolver, ip addresses are never resolved
+            let ip = host.trim_start_matches('[').trim_end_matches(']').parse();
+            if matches!(ip, Ok(ip) if is_private_ip(ip)) {
+                bail!("{} is a private address", host);
+            }
+        }
+        Ok(())
+    }
+
+    /// redirect policy which checks every hop against this policy
+    pub(crate) fn redirect_policy(&self) -> redirect::Policy {
+        let policy = self.clone();
+        redirect::Policy::custom(move |attempt| {
+            // `previous` includes the url of the original request
+            if policy.max_redirects == 0 {
+                attempt.stop()
+            } else if attempt.previous().len() > policy.max_redirects {
+                attempt.error(format!("more than {} redirects", policy.max_redirects))
+            } else if let Err(e) = policy.check_url(attempt.url()) {
+                attempt.error(e.to_string())
+            } else {
+                attempt.follow()
+            }
+        })
+    }
+
+    /// dns resolver which drops the private addresses, if they are blocked
+    pub(crate) fn resolver(&self) -> Option<PublicResolver> {
+        self.block_private_ips.then_some(PublicResolver)
+    }
+}
+
+/// Resolves host names to their public addresses only, so that a public name pointing to an
+/// internal address could not be used to reach the internal network.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|addr| !is_private_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                let e = format!("{} does not resolve to a public address", host);
+                return Err(e.into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        _ if pattern == "*" => true,
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_ipv4(ip),
+        IpAddr::V6(ip) => embedded_ipv4(ip).is_some_and(is_private_ipv4) || is_private_ipv6(ip),
+    }
+}
+
+/// the ipv4 address embedded in the ipv6 one, which could be used to reach it
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let [a, b, c, d, e, f, g, h] = ip.segments();
+    let ipv4 = |high: u16, low: u16| {
+        let [x, y] = high.to_be_bytes();
+        let [z, w] = low.to_be_bytes();
+        Ipv4Addr::new(x, y, z, w)
+    };
+    match (a, b, c, d, e, f) {
+        // ipv4-mapped (::ffff:0:0/96) and ipv4-compatible (::/96)
+        (0, 0, 0, 0, 0, 0xffff | 0) => Some(ipv4(g, h)),
+        // nat64 (64:ff9b::/96)
+        (0x64, 0xff9b, 0, 0, 0, 0) => Some(ipv4(g, h)),
+        // 6to4 (2002::/16)
+        (0x2002, ..) => Some(ipv4(b, c)),
+        _ => None,
+    }
+}
+
+fn is_private_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // shared address space (100.64.0.0/10) and "this network" (0.0.0.0/8)
+        || (a == 100 && (64..128).contains(&b))
+        || a == 0
+        // reserved (240.0.0.0/4) and benchmarking (198.18.0.0/15)
+        || a >= 240
+        || (a == 198 && (b & 0xfe) == 18)
+}
+
+fn is_private_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // unique local (fc00::/7), link-local (fe80::/10) and site-local (fec0::/10)
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || (first & 0xffc0) == 0xfec0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fetch_policy_should_check_urls() {
+        let url = |s: &str| Url::parse(s).expect("valid");
+        let policy = FetchPolicy::new()
+            .allowed_schemes(["https"])
+            .allow_host("example.com")
+            .allow_host("*.api.example.com")
+            .block_private_ips(true);
+
+        assert!(policy.check_url(&url("https://example.com/a")).is_ok());
+        assert!(policy.check_url(&url("https://EU.api.example.com")).is_ok());
+        assert!(policy.check_url(&url("http://example.com")).is_err());
+        assert!(policy.check_url(&url("https://api.example.com")).is_err());
+        assert!(policy
+            .check_url(&url("https://evilapi.example.com"))
+            .is_err());
+        assert!(policy.check_url(&url("https://example.org")).is_err());
+
+        let policy = FetchPolicy::new().block_private_ips(true);
+        assert!(policy.check_url(&url("http://8.8.8.8")).is_ok());
+        for private in [
+            "http://127.0.0.1:8080",
+            "http://10.1.2.3",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]",
+            "http://[::ffff:192.168.1.1]",
+            "http://[fd00::1]",
+            "http://224.0.0.1",
+            "http://[ff02::1]",
+            "http://240.0.0.1",
+            "http://198.18.0.1",
+            "http://[fec0::1]",
+            "http://[::127.0.0.1]",
+            "http://[64:ff9b::10.0.0.1]",
+            "http://[2002:a9fe:a9fe::1]",
+        ] {
+            assert!(policy.check_url(&url(private)).is_err(), "{}", private);
+        }
+        for public in ["http://[64:ff9b::8.8.8.8]", "http://[2002:808:808::1]"] {
+            assert!(policy.check_url(&url(public)).is_ok(), "{}", public);
+        }
+        assert!(FetchPolicy::new()
+            .check_url(&url("http://127.0.0.1"))
+            .is_ok());
+    }
+
+    #[test]
+    fn fetch_policy_should_cap_timeout() {
+        let policy = FetchPolicy::new().timeout(Duration::from_secs(5));
+        let timeout = |ms| policy.request_timeout(Some(Duration::from_millis(ms)));
+        assert_eq!(timeout(100), Some(Duration::from_millis(100)));
+        assert_eq!(timeout(10_000), Some(Duration::from_secs(5)));
+        assert_eq!(policy.request_timeout(None), Some(Duration::from_secs(5)));
+        assert_eq!(FetchPolicy::new().request_timeout(None), None);
+    }
+}
@@ -0,0 +1,114 @@
+// snapshot and restore the own properties of the global object, and freeze the builtins. The
+// functions used are captured here, so that scripts could not interfere by patching `Object`,
+// `Reflect` or the iterators.
+(function () {
+  const { defineProperty, freeze, getOwnPropertyDescriptor, getPrototypeOf } = Object;
+  const { deleteProperty, ownKeys } = Reflect;
+  const global = globalThis;
+  // globals whose state is changed by the host
+  const mutable = ["globalThis"];
+  // host objects whose native methods are on their prototype, e.g. `dispatcher.send`
+  const hosted = ["dispatcher"];
+
+  let keys = [];
+  let descriptors = [];
+
+  const same = (a, b) =>
+    a.value === b.value &&
+    a.get === b.get &&
+    a.set === b.set &&
+    a.writable === b.writable &&
+    a.enumerable === b.enumerable &&
+    a.configurable === b.configurable;
+
+  const indexOf = (array, key) => {
+    for (let i = 0; i < array.length; i++) {
+      if (array[i] === key) {
+        return i;
+      }
+    }
+    return -1;
+  };
+
+  const contains = (array, key) => indexOf(array, key) !== -1;
+
+  return {
+    snapshot() {
+      keys = ownKeys(global);
+      descriptors = [];
+      for (let i = 0; i < keys.length; i++) {
+        descriptors[i] = getOwnPropertyDescriptor(global, keys[i]);
+      }
+    },
+
+    // record the given globals only, as the others could be changed by the runs in progress
+    record(...names) {
+      for (let i = 0; i < names.length; i++) {
+        const descriptor = getOwnPropertyDescriptor(global, names[i]);
+        const index = indexOf(keys, names[i]);
+        if (descriptor === undefined && index !== -1) {
+          const kept = [];
+          const keptDescriptors = [];
+          for (let j = 0; j < keys.length; j++) {
+            if (j !== index) {
+              kept[kept.length] = keys[j];
+              keptDescriptors[keptDescriptors.length] = descriptors[j];
+            }
+          }
+          keys = kept;
+          descriptors = keptDescriptors;
+        } else if (descriptor !== undefined) {
+          const at = index === -1 ? keys.length : index;
+          keys[at] = names[i];
+          descriptors[at] = descriptor;
+        }
+      }
+    },
+
+    // returns the names of the globals which could not be restored, as the script made them
+    // non-configurable
+    restore() {
+      const failed = [];
+      const current = ownKeys(global);
+      for (let i = 0; i < current.length; i++) {
+        if (!contains(keys, current[i]) && !deleteProperty(global, current[i])) {
+          failed[failed.length] = String(current[i]);
+        }
+      }
+      for (let i = 0; i < keys.length; i++) {
+        const descriptor = getOwnPropertyDescriptor(global, keys[i]);
+        if (descriptor === undefined || !same(descriptor, descriptors[i])) {
+          try {
+            defineProperty(global, keys[i], descriptors[i]);
+          } catch {
+            failed[failed.length] = String(keys[i]);
+          }
+        }
+      }
+      return failed;
+    },
+
+    freeze() {
+      const current = ownKeys(global);
+      for (let i = 0; i < current.length; i++) {
+        const key = current[i];
+        if (contains(mutable, key)) {
+          continue;
+        }
+        const descriptor = getOwnPropertyDescriptor(global, key);
+        if ("value" in descriptor) {
+          defineProperty(global, key, { writable: false, configurable: false });
+          const value = descriptor.value;
+          if (typeof value === "function" || (typeof value === "object" && value !== null)) {
+            freeze(value);
+          }
+          if (contains(hosted, key)) {
+            freeze(getPrototypeOf(value));
+          }
+        } else {
+          defineProperty(global, key, { configurable: false });
+        }
+      }
+    },
+  };
+});
@@ -0,0 +1,83 @@
+use js::{Ctx, FromJs, Function, Object, Persistent};
+
+/// js implementation of the snapshot, restoring and freezing of the global object
+const ISOLATION_JS: &str = include_str!("isolation.js");
+
+/// Keeps the global object of an engine as the host set it up, see
+/// `JsEngineBuilder::isolate_globals` and `JsEngineBuilder::freeze_builtins`.
+#[derive(Debug)]
+pub(crate) struct Isolation {
+    /// the `{ snapshot, restore, freeze }` object created by `isolation.js`
+    globals: Option<Persistent<Object<'static>>>,
+    /// whether the globals are recorded, so that they could be restored
+    restore: bool,
+    /// whether the globals are restored after the runs, rather than only when a pooled engine
+    /// is returned
+    after_runs: bool,
+}
+
+impl Isolation {
+    pub(crate) fn new(ctx: Ctx<'_>, restore: bool, after_runs: bool) -> Result<Self, js::Error> {
+        let create: Function = ctx.eval(ISOLATION_JS)?;
+        let globals: Object = create.call(())?;
+        Ok(Self {
+            globals: Some(Persistent::save(ctx, globals)),
+            restore,
+            after_runs,
+        })
+    }
+
+    /// whether the globals are restored after the runs
+    pub(crate) fn after_runs(&self) -> bool {
+        self.after_runs
+    }
+
+    /// make the current globals read-only and freeze their values
+    pub(crate) fn freeze(&self, ctx: Ctx<'_>) -> Result<(), js::Error> {
+        self.call(ctx, "freeze", Vec::new())
+    }
+
+    /// record the current globals as the state to restore after the runs
+    pub(crate) fn snapshot(&self, ctx: Ctx<'_>) -> Result<(), js::Error> {
+        match self.restore {
+            true => self.call(ctx, "snapshot", Vec::new()),
+            false => Ok(()),
+        }
+    }
+
+    /// record the current value of the given globals only, e.g. while runs are in progress
+    pub(crate) fn record(&self, ctx: Ctx<'_>, names: Vec<String>) -> Result<(), js::Error> {
+        match self.restore {
+            true => self.call(ctx, "record", names),
+            false => Ok(()),
+        }
+    }
+
+    /// restore the globals to the snapshot, after the runs. Returns the names of the globals
+    /// which could not be restored, as the scripts made them non-configurable.
+    pub(crate) fn restore(&self, ctx: Ctx<'_>) -> Result<Vec<String>, js::Error> {
+        match self.restore {
+            true => self.call(ctx, "restore", Vec::new()),
+            false => Ok(Vec::new()),
+        }
+    }
+
+    /// release the persistent object, which must be done while holding the runtime lock
+    pub(crate) fn release(&mut self, ctx: Ctx<'_>) {
+        if let Some(globals) = self.globals.take() {
+            drop(globals.restore(ctx));
+        }
+    }
+
+    fn call<'js, R>(&self, ctx: Ctx<'js>, method: &str, args: Vec<String>) -> Result<R, js::Error>
+    where
+        R: FromJs<'js> + Default,
+    {
+        let globals = match &self.globals {
+            Some(globals) => globals.clone().restore(ctx)?,
+            None => return Ok(R::default()),
+        };
+        let method = globals.get::<_, Function>(method)?;
+        method.call((js::This(globals), js::Rest(args)))
+    }
+}
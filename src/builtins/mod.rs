@@ -4,6 +4,10 @@ pub(crate) mod console;
 pub(crate) mod dispatcher;
 #[cfg(feature = "fetch")]
 pub(crate) mod fetch;
+pub(crate) mod isolation;
+pub(crate) mod native;
+#[cfg(feature = "timers")]
+pub(crate) mod timers;
 
 #[cfg(feature = "console")]
 pub(crate) use console::*;
@@ -11,3 +15,5 @@ pub(crate) use console::*;
 pub(crate) use dispatcher::*;
 #[cfg(feature = "fetch")]
 pub(crate) use fetch::*;
+#[cfg(feature = "timers")]
+pub(crate) use timers::*;
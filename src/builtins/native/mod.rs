@@ -0,0 +1,92 @@
+use std::future::Future;
+
+use crate::{JsonOptions, JsonValue, ProcessorError};
+use js::{Async, Ctx, Func, Function, IntoJs};
+use serde_json::json;
+
+/// js function installing the registered native functions
+const NATIVE_JS: &str = include_str!("native.js");
+
+/// What a native function returns to `native.js`, i.e. `{ ok }` or `{ error }`.
+struct Outcome(Result<JsonValue, ProcessorError>, JsonOptions);
+
+/// Install the sync function at the (dotted) path, e.g. `crypto.sign`. The arguments are
+/// passed to the function as an array, and its error is thrown as an `Error` with `code` and
+/// `details`.
+pub(crate) fn register_fn<'js, F, E>(
+    ctx: Ctx<'js>,
+    path: &str,
+    f: F,
+    options: JsonOptions,
+) -> Result<(), js::Error>
+where
+    F: Fn(JsonValue) -> Result<JsonValue, E> + Send + Sync + 'static,
+    E: Into<ProcessorError>,
+{
+    let native = move |ctx: Ctx<'js>, args: js::Value<'js>| -> Result<Outcome, js::Error> {
+        let args = JsonValue::from_js_with(ctx, args, &options)?;
+        Ok(Outcome(f(args).map_err(Into::into), options))
+    };
+    install(ctx, path, Func::new(name(path), native), false)
+}
+
+/// Install the async function at the (dotted) path like [`register_fn`]. It returns a
+/// promise in javascript.
+pub(crate) fn register_async_fn<'js, F, Fut, E>(
+    ctx: Ctx<'js>,
+    path: &str,
+    f: F,
+    options: JsonOptions,
+) -> Result<(), js::Error>
+where
+    F: Fn(JsonValue) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<JsonValue, E>> + Send + 'static,
+    E: Into<ProcessorError>,
+{
+    let native = move |ctx: Ctx<'js>, args: js::Value<'js>| {
+        // the arguments are converted before the promise is created
+        let ret = JsonValue::from_js_with(ctx, args, &options)
+            .map(&f)
+            .map_err(|e| ProcessorError::new(e.to_string()));
+        async move {
+            let ret = match ret {
+                Ok(fut) => fut.await.map_err(Into::into),
+                Err(e) => Err(e),
+            };
+            Outcome(ret, options)
+        }
+    };
+    install(ctx, path, Func::new(name(path), Async(native)), true)
+}
+
+fn install<'js, F: IntoJs<'js>>(
+    ctx: Ctx<'js>,
+    path: &str,
+    native: F,
+    is_async: bool,
+) -> Result<(), js::Error> {
+    let install: Function = ctx.eval(NATIVE_JS)?;
+    install.call((path, native, is_async))
+}
+
+/// the name of the function, without the namespaces
+fn name(path: &str) -> &str {
+    path.rsplit('.').next().unwrap_or(path)
+}
+
+impl<'js> IntoJs<'js> for Outcome {
+    fn into_js(self, ctx: Ctx<'js>) -> Result<js::Value<'js>, js::Error> {
+        let Self(ret, options) = self;
+        let value = match ret {
+            Ok(value) => json!({ "ok": value.0 }),
+            Err(e) => json!({
+                "error": {
+                    "message": e.message,
+                    "code": e.code,
+                    "details": e.details.map(|details| details.0),
+                }
+            }),
+        };
+        JsonValue(value).into_js_with(ctx, &options)
+    }
+}
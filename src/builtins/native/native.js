@@ -0,0 +1,34 @@
+// install a function registered by `JsEngine::register_fn` or `register_async_fn` at the
+// (dotted) path. The native function takes the arguments as an array, and returns (or
+// resolves to) `{ ok }` or `{ error }`.
+(function (path, native, isAsync) {
+  // strict, so that a frozen namespace, e.g. `Math` under `freeze_builtins`, or a primitive
+  // fails the registration instead of ignoring the function
+  "use strict";
+
+  const unwrap = (res) => {
+    if ("error" in res) {
+      const error = new Error(res.error.message);
+      error.function = path;
+      error.code = res.error.code;
+      error.details = res.error.details;
+      throw error;
+    }
+    return res.ok;
+  };
+  const fn = isAsync
+    ? async (...args) => unwrap(await native(args))
+    : (...args) => unwrap(native(args));
+
+  const names = path.split(".");
+  const name = names.pop();
+  let target = globalThis;
+  for (const namespace of names) {
+    target[namespace] ??= {};
+    target = target[namespace];
+  }
+  target[name] = fn;
+  if (target[name] !== fn) {
+    throw new TypeError(`the function could not be installed at ${path}`);
+  }
+});
@@ -0,0 +1,67 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use js::{Async, Ctx, Func, Object};
+use tokio::sync::oneshot;
+
+use crate::sync::lock;
+
+/// js implementation of `setTimeout`, `setInterval`, `clearTimeout`, `clearInterval` and
+/// `queueMicrotask` on top of `__timers`
+pub(crate) const TIMERS_JS: &str = include_str!("timers.js");
+
+/// The pending timers of an engine, cancelled when the run finishes.
+#[derive(Debug, Default)]
+pub(crate) struct Timers {
+    pending: Mutex<HashMap<u32, oneshot::Sender<()>>>,
+}
+
+/// Create the `__timers` object, the native side of the timers captured by `timers.js`.
+pub(crate) fn timers_object<'js>(
+    ctx: Ctx<'js>,
+    timers: Arc<Timers>,
+) -> Result<Object<'js>, js::Error> {
+    let obj = Object::new(ctx)?;
+    // resolve to true once the delay (in milliseconds) elapsed, or false if cancelled
+    let sleep = {
+        let timers = timers.clone();
+        move |id: u32, delay: f64| {
+            let timers = timers.clone();
+            async move { timers.sleep(id, delay).await }
+        }
+    };
+    obj.set("sleep", Func::new("sleep", Async(sleep)))?;
+    let clear = move |id: u32| timers.clear(id);
+    obj.set("clear", Func::new("clear", clear))?;
+    Ok(obj)
+}
+
+impl Timers {
+    async fn sleep(&self, id: u32, delay: f64) -> bool {
+        let (tx, rx) = oneshot::channel();
+        lock(&self.pending).insert(id, tx);
+        // `timers.js` ensures the delay is not negative, so only a huge delay could fail here
+        let delay = Duration::try_from_secs_f64(delay / 1000.0).unwrap_or(Duration::MAX);
+        let fired = tokio::select! {
+            _ = tokio::time::sleep(delay) => true,
+            _ = rx => false,
+        };
+        if fired {
+            lock(&self.pending).remove(&id);
+        }
+        fired
+    }
+
+    fn clear(&self, id: u32) {
+        // dropping the sender cancels the timer
+        lock(&self.pending).remove(&id);
+    }
+
+    /// cancel all the pending timers, e.g. when the run finishes
+    pub(crate) fn clear_all(&self) {
+        lock(&self.pending).clear();
+    }
+}
@@ -0,0 +1,57 @@
+(function (native) {
+  // ids of the timers which are neither fired (timeouts) nor cleared
+  const active = new Set();
+  let nextId = 1;
+
+  function schedule(id, delay, repeat, callback, args) {
+    native.sleep(id, delay).then((fired) => {
+      // not fired if cleared or the run is finished
+      if (!fired || !active.has(id)) {
+        active.delete(id);
+        return;
+      }
+      if (!repeat) {
+        active.delete(id);
+      }
+      callback(...args);
+      if (repeat && active.has(id)) {
+        schedule(id, delay, repeat, callback, args);
+      }
+    });
+  }
+
+  function create(callback, delay, repeat, args) {
+    if (typeof callback !== "function") {
+      throw new TypeError("The callback must be a function");
+    }
+    const id = nextId++;
+    delay = Number(delay);
+    delay = Number.isFinite(delay) && delay > 0 ? delay : 0;
+    // avoid busy loops of intervals without delay
+    if (repeat) {
+      delay = Math.max(delay, 1);
+    }
+    active.add(id);
+    schedule(id, delay, repeat, callback, args);
+    return id;
+  }
+
+  function clear(id) {
+    if (active.delete(id)) {
+      native.clear(id);
+    }
+  }
+
+  globalThis.setTimeout = (callback, delay, ...args) => create(callback, delay, false, args);
+  globalThis.setInterval = (callback, delay, ...args) => create(callback, delay, true, args);
+  globalThis.clearTimeout = clear;
+  globalThis.clearInterval = clear;
+  globalThis.queueMicrotask = (callback) => {
+    if (typeof callback !== "function") {
+      throw new TypeError("The callback must be a function");
+    }
+    Promise.resolve().then(() => callback());
+  };
+})(globalThis.__timers);
+
+delete globalThis.__timers;
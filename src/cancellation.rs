@@ -3,10 +3,13 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 
-use crate::Cancellation;
+use crate::{Cancellation, Error};
+
+/// how often the cancellation flag is checked while waiting for a pending promise
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 impl Cancellation {
     pub fn new(deadline: Option<Instant>, cancellation: Arc<AtomicBool>) -> Cancellation {
@@ -16,8 +19,41 @@ impl Cancellation {
         }
     }
 
+    /// Create a cancellation which expires after the given timeout.
+    pub fn with_timeout(timeout: Duration) -> Cancellation {
+        Self::new(Some(Instant::now() + timeout), Default::default())
+    }
+
+    /// Cancel the execution. All clones of this cancellation share the same flag.
+    pub fn cancel(&self) {
+        self.cancellation.store(true, Ordering::Relaxed);
+    }
+
     pub fn cancelled(&self) -> bool {
+        self.timed_out() || self.cancellation.load(Ordering::Relaxed)
+    }
+
+    fn timed_out(&self) -> bool {
         self.deadline.map(|d| d <= Instant::now()).unwrap_or(false)
-            || self.cancellation.load(Ordering::Relaxed)
+    }
+
+    /// The error to report once the execution is cancelled.
+    pub(crate) fn error(&self) -> Error {
+        if self.cancellation.load(Ordering::Relaxed) {
+            Error::Cancelled
+        } else {
+            Error::Timeout
+        }
+    }
+
+    /// Resolve once the cancellation is triggered, either by the flag or the deadline.
+    pub(crate) async fn wait(&self) {
+        while !self.cancelled() {
+            let interval = match self.deadline {
+                Some(d) => POLL_INTERVAL.min(d.saturating_duration_since(Instant::now())),
+                None => POLL_INTERVAL,
+            };
+            tokio::time::sleep(interval).await;
+        }
     }
 }
@@ -1,66 +1,193 @@
-use crate::{error::*, JsEngine, JsonValue};
-use std::fmt;
+use crate::{
+    error::*,
+    exception::{js_error, PendingResult},
+    script::wrap_code,
+    Cancellation, JsEngine, JsEngineBuilder, JsonValue, RunOutput,
+};
+use std::{fmt, sync::atomic::Ordering};
 
-use js::{Context, Function, Object, Promise, Tokio};
+use js::{Function, Object};
+use serde::{de::DeserializeOwned, Serialize};
 use snafu::ResultExt;
-use tracing::debug;
+use tracing::{debug, warn};
 impl JsEngine {
+    pub fn builder() -> JsEngineBuilder {
+        JsEngineBuilder::new()
+    }
+
     #[cfg(feature = "dispatcher")]
     pub fn create() -> Result<(Self, flume::Receiver<crate::MsgChannel>)> {
         let (tx, rx) = flume::unbounded::<crate::MsgChannel>();
-        let rt = js::Runtime::new().context(JsRuntimeSnafu)?;
-        rt.set_max_stack_size(256 * 1024);
-        rt.set_memory_limit(2 * 1024 * 1024);
-
-        let ctx = Context::full(&rt).context(JsContextSnafu)?;
-        rt.spawn_executor(Tokio);
-
-        let engine = Self {
-            runtime: rt,
-            context: ctx,
-            sender: tx,
-        };
-        engine.init_globals()?;
+        let engine = Self::builder().dispatcher(tx).build()?;
         Ok((engine, rx))
     }
 
     #[cfg(not(feature = "dispatcher"))]
     pub fn create() -> Result<Self> {
-        let rt = js::Runtime::new().context(JsRuntimeSnafu)?;
-        rt.set_max_stack_size(256 * 1024);
-        rt.set_memory_limit(2 * 1024 * 1024);
-
-        let ctx = Context::full(&rt).context(JsContextSnafu)?;
-        rt.spawn_executor(Tokio);
-
-        let engine = Self {
-            runtime: rt,
-            context: ctx,
-        };
-        engine.init_globals()?;
-        Ok(engine)
+        Self::builder().build()
     }
 
     #[cfg(feature = "builtin_processor")]
     pub fn create_with_processors(
         processors: Vec<(&str, &str, Box<dyn crate::Processor>)>,
     ) -> Result<Self, Error> {
-        let (engine, rx) = Self::create()?;
+        Self::create_with_registry(processors.into_iter().collect())
+    }
+
+    #[cfg(feature = "builtin_processor")]
+    pub fn create_with_registry(registry: crate::ProcessorRegistry) -> Result<Self, Error> {
+        Self::builder().processors(registry).build()
+    }
+
+    /// Expose the processors in the registry as global namespace objects, so that scripts
+    /// could call `await auth.create_token(args)`. It is called when the engine is built, and
+    /// should be called again after the registry is updated.
+    ///
+    /// A namespace never shadows an existing global, e.g. `console`. The other namespaces are
+    /// exposed, then [`Error::NamespaceConflict`] is returned with the skipped ones, also by
+    /// [`JsEngineBuilder::build`].
+    #[cfg(feature = "builtin_processor")]
+    pub fn expose_processors(&self) -> Result<()> {
+        let registry = match &self.processors {
+            Some(registry) => registry,
+            None => return Ok(()),
+        };
+        let mut processors = serde_json::Map::new();
+        for (ns, name) in registry.names() {
+            let methods = processors
+                .entry(ns)
+                .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+            if let serde_json::Value::Array(methods) = methods {
+                methods.push(name.into());
+            }
+        }
+
+        let ret: Result<Vec<String>, js::Error> = self.context.with(|ctx| {
+            let expose = match &*crate::sync::lock(&self.expose) {
+                Some(expose) => expose.clone().restore(ctx)?,
+                None => return Ok(Vec::new()),
+            };
+            let exposed: Object = expose.call((JsonValue(processors.into()),))?;
+            self.snapshot_globals(ctx, exposed.get("changed")?)?;
+            exposed.get("skipped")
+        });
+        let skipped = ret.map_err(js_error)?;
+        snafu::ensure!(
+            skipped.is_empty(),
+            NamespaceConflictSnafu {
+                namespaces: skipped
+            }
+        );
+        Ok(())
+    }
 
-        run_processors(rx, processors);
-        Ok(engine)
+    /// The processor registry used by the engine, if it is created with one.
+    #[cfg(feature = "builtin_processor")]
+    pub fn processors(&self) -> Option<&crate::ProcessorRegistry> {
+        self.processors.as_ref()
     }
 
     pub async fn run(&self, code: &str, req: JsonValue) -> Result<JsonValue, Error> {
-        let ret: Result<Promise<JsonValue>, js::Error> = self.context.with(|ctx| {
-            let src = format!(r#"export default async function(req) {{ {} }}"#, code);
+        let run = self.begin_run("script");
+        self.run_code(&run, code, req).await
+    }
+
+    /// Run the code like [`JsEngine::run`], but serialize the request into and deserialize the
+    /// result from javascript values directly, without the intermediate [`JsonValue`].
+    pub async fn run_typed<Req, Res>(&self, code: &str, req: &Req) -> Result<Res>
+    where
+        Req: Serialize + ?Sized,
+        Res: DeserializeOwned + Send + 'static,
+    {
+        let run = self.begin_run("script");
+        let options = self.json;
+        let ret: Result<PendingResult<Res>, js::Error> = self.context.with(|ctx| {
+            let src = wrap_code(code);
             debug!("code to execute: {}", src);
             let m = ctx.compile("script", src)?;
             let fun = m.get::<_, Function>("default")?;
 
-            fun.call((req,))
+            let req = crate::to_js(ctx, req, &options)?;
+            let ret = fun.call((req, run.console(ctx)?))?;
+            PendingResult::with(ctx, ret, move |ctx, value| {
+                crate::from_js(ctx, value, &options)
+            })
+        });
+        ret.map_err(js_error)?.wait().await
+    }
+
+    /// Evaluate the source as an ES module named `module`, then call its exported function
+    /// `entry`, e.g. `handler` for `export async function handler(req, env) {}`, with the
+    /// arguments. Unlike [`JsEngine::run`], the source is not wrapped, so it could declare
+    /// imports, helpers and other exports at the top level.
+    pub async fn run_module(
+        &self,
+        source: &str,
+        entry: &str,
+        args: Vec<JsonValue>,
+    ) -> Result<JsonValue> {
+        let _run = self.begin_run("module");
+        let ret: Result<PendingResult, js::Error> = self.context.with(|ctx| {
+            let m = ctx.compile("module", source)?;
+            let fun = match m.get::<_, js::Value>(entry)? {
+                v if v.is_function() => v.into_function().expect("checked function"),
+                v => {
+                    let message = format!("the module has no function export `{}`", entry);
+                    return Err(js::Error::new_from_js_message(
+                        v.type_name(),
+                        "function",
+                        message,
+                    ));
+                }
+            };
+            let args = args
+                .into_iter()
+                .map(|arg| arg.into_js_with(ctx, &self.json))
+                .collect::<Result<Vec<_>, _>>()?;
+            PendingResult::new(ctx, fun.call((js::Rest(args),))?, self.json)
         });
-        ret.context(JsExecuteSnafu)?.await.context(JsExecuteSnafu)
+        ret.map_err(js_error)?.wait().await
+    }
+
+    /// Run the code like [`JsEngine::run`], and capture what the script writes to `console`
+    /// during the run. Each run has its own `console`, so the output of the other runs on the
+    /// same engine at the same time is not captured.
+    pub async fn run_captured(&self, code: &str, req: JsonValue) -> Result<RunOutput> {
+        let run = self.begin_run("script");
+        run.capture();
+        let value = self.run_code(&run, code, req).await?;
+        let logs = run.captured();
+        Ok(RunOutput { value, logs })
+    }
+
+    /// Run the code like [`JsEngine::run`], but interrupt the execution once the cancellation
+    /// is triggered. Returns [`Error::Cancelled`] or [`Error::Timeout`] accordingly.
+    ///
+    /// The interrupt handler belongs to the runtime, so the cancellable runs should not overlap
+    /// on the same engine, e.g. use a [`crate::JsEnginePool`] instead: the latest run replaces
+    /// the handler of the others, and the first one to finish removes it. The other runs are
+    /// then still cancelled while they wait for a promise, but not inside a busy loop.
+    pub async fn run_with_cancellation(
+        &self,
+        code: &str,
+        req: JsonValue,
+        cancellation: Cancellation,
+    ) -> Result<JsonValue, Error> {
+        let handler = cancellation.clone();
+        self.runtime
+            .set_interrupt_handler(Some(Box::new(move || handler.cancelled())));
+        // removes the handler even if the future is dropped before the run finishes
+        let _interrupt = InterruptGuard(self);
+
+        let ret = tokio::select! {
+            ret = self.run(code, req) => ret,
+            _ = cancellation.wait() => Err(cancellation.error()),
+        };
+
+        match ret {
+            Err(_) if cancellation.cancelled() => Err(cancellation.error()),
+            ret => ret,
+        }
     }
 
     pub fn load_global_js(&self, name: &str, code: &str) -> Result<()> {
@@ -68,35 +195,216 @@ impl JsEngine {
             let global = ctx.globals();
             let m = ctx.compile(name, code)?;
             let obj = m.get::<_, Object>("default")?;
+            let mut names = Vec::new();
             for item in obj.into_iter() {
                 let (k, v) = item?;
-                global.set(k, v)?;
+                global.set(k.clone(), v)?;
+                names.push(k.to_string()?);
             }
-            Ok(())
+            self.snapshot_globals(ctx, names)
         });
-        ret.context(JsExecuteSnafu)
+        ret.map_err(js_error)
+    }
+
+    /// Set the global variable, e.g. to inject the configuration read by the scripts. The
+    /// variable lives as long as the engine.
+    pub fn set_global(&self, name: &str, value: JsonValue) -> Result<()> {
+        let ret: Result<(), js::Error> = self.context.with(|ctx| {
+            let value = value.into_js_with(ctx, &self.json)?;
+            ctx.globals().set(name, value)?;
+            self.snapshot_globals(ctx, vec![name.to_owned()])
+        });
+        ret.map_err(js_error)
+    }
+
+    /// The value of the global variable, or `None` if it is not defined.
+    pub fn get_global(&self, name: &str) -> Result<Option<JsonValue>> {
+        let ret: Result<_, js::Error> = self.context.with(|ctx| {
+            let globals = ctx.globals();
+            if !globals.contains_key(name)? {
+                return Ok(None);
+            }
+            let value = globals.get::<_, js::Value>(name)?;
+            JsonValue::from_js_with(ctx, value, &self.json).map(Some)
+        });
+        ret.map_err(js_error)
+    }
+
+    /// Remove the global variable. Removing an undefined variable does nothing.
+    pub fn remove_global(&self, name: &str) -> Result<()> {
+        let ret: Result<(), js::Error> = self.context.with(|ctx| {
+            ctx.globals().remove(name)?;
+            self.snapshot_globals(ctx, vec![name.to_owned()])
+        });
+        ret.map_err(js_error)
+    }
+
+    /// Call the global function, e.g. a helper defined by [`JsEngine::load_global_js`], with
+    /// the arguments, and wait for its result if it returns a promise.
+    pub async fn call_function(&self, name: &str, args: Vec<JsonValue>) -> Result<JsonValue> {
+        let _run = self.begin_run(name);
+        let ret: Result<PendingResult, js::Error> = self.context.with(|ctx| {
+            let globals = ctx.globals();
+            let fun = globals.get::<_, Function>(name)?;
+            let args = args
+                .into_iter()
+                .map(|arg| arg.into_js_with(ctx, &self.json))
+                .collect::<Result<Vec<_>, _>>()?;
+            // called from an async function, so that what it throws is rejected with the
+            // thrown object, e.g. a `TypeError`, instead of reported by rquickjs as an `Error`
+            let call: Function = ctx.eval("(async (f, self, args) => f.apply(self, args))")?;
+            let ret = call.call((fun, globals, args))?;
+            PendingResult::new(ctx, ret, self.json)
+        });
+        ret.map_err(js_error)?.wait().await
+    }
+
+    /// Install the rust function as a global function, or under namespace objects if the name
+    /// is dotted, e.g. `crypto.sign` for `crypto.sign(data, key)`. The function receives the
+    /// arguments as an array, and its error is thrown in javascript as an `Error` with the
+    /// `code` and `details` of the [`ProcessorError`](crate::ProcessorError). Fails if the
+    /// function could not be installed, e.g. under a frozen namespace such as `Math` once the
+    /// builtins are frozen.
+    pub fn register_fn<F, E>(&self, name: &str, f: F) -> Result<()>
+    where
+        F: Fn(JsonValue) -> Result<JsonValue, E> + Send + Sync + 'static,
+        E: Into<crate::ProcessorError>,
+    {
+        let ret: Result<(), js::Error> = self.context.with(|ctx| {
+            crate::builtins::native::register_fn(ctx, name, f, self.json)?;
+            self.snapshot_globals(ctx, vec![root(name)])
+        });
+        ret.map_err(js_error)
+    }
+
+    /// Install the async rust function like [`JsEngine::register_fn`]. It returns a promise in
+    /// javascript.
+    pub fn register_async_fn<F, Fut, E>(&self, name: &str, f: F) -> Result<()>
+    where
+        F: Fn(JsonValue) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<JsonValue, E>> + Send + 'static,
+        E: Into<crate::ProcessorError>,
+    {
+        let ret: Result<(), js::Error> = self.context.with(|ctx| {
+            crate::builtins::native::register_async_fn(ctx, name, f, self.json)?;
+            self.snapshot_globals(ctx, vec![root(name)])
+        });
+        ret.map_err(js_error)
     }
 
-    fn init_globals(&self) -> Result<(), Error> {
+    /// run the code as the body of the handler function, see [`JsEngine::run`]
+    async fn run_code(&self, run: &RunGuard<'_>, code: &str, req: JsonValue) -> Result<JsonValue> {
+        let ret: Result<PendingResult, js::Error> = self.context.with(|ctx| {
+            let src = wrap_code(code);
+            debug!("code to execute: {}", src);
+            let m = ctx.compile("script", src)?;
+            let fun = m.get::<_, Function>("default")?;
+
+            let req = req.into_js_with(ctx, &self.json)?;
+            PendingResult::new(ctx, fun.call((req, run.console(ctx)?))?, self.json)
+        });
+        ret.map_err(js_error)?.wait().await
+    }
+
+    /// reset the per-run state of the builtins, e.g. the number of requests sent by `fetch`,
+    /// for the script of the given name. The returned guard ends the run when dropped, i.e.
+    /// when the run finishes or is cancelled.
+    #[allow(unused_variables)]
+    pub(crate) fn begin_run(&self, script: &str) -> RunGuard<'_> {
+        #[cfg(feature = "fetch")]
+        if let Some(fetcher) = &self.fetcher {
+            fetcher.reset();
+        }
+        self.runs.fetch_add(1, Ordering::SeqCst);
+        self.started.fetch_add(1, Ordering::Relaxed);
+        RunGuard {
+            engine: self,
+            #[cfg(feature = "console")]
+            console: self
+                .console
+                .as_ref()
+                .map(|console| console.begin_run(script)),
+        }
+    }
+
+    /// cancel what the runs left behind, e.g. the pending timers, and restore the globals once
+    /// the last of the overlapping runs ends, as the others could still wait for their timers
+    fn end_run(&self) {
+        if self.runs.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+        #[cfg(feature = "timers")]
+        if let Some(timers) = &self.timers {
+            timers.clear_all();
+        }
+        if matches!(&self.isolation, Some(isolation) if isolation.after_runs()) {
+            self.reset_globals();
+        }
+    }
+
+    /// restore the globals to what the host set up, after the runs or when a pooled engine is
+    /// returned. The globals which could not be restored are reported as a warning.
+    pub(crate) fn reset_globals(&self) {
+        if let Some(isolation) = &self.isolation {
+            match self.context.with(|ctx| isolation.restore(ctx)) {
+                Ok(kept) if !kept.is_empty() => {
+                    warn!("the globals could not be restored: {}", kept.join(", "));
+                }
+                Ok(_) => {}
+                Err(e) => warn!("failed to restore the globals: {}", e),
+            }
+        }
+    }
+
+    /// record the globals changed by the host, given by name, so that they are kept after the
+    /// runs. While runs are in progress, only the given globals are recorded, as the others
+    /// could be changed by the scripts.
+    fn snapshot_globals(&self, ctx: js::Ctx<'_>, names: Vec<String>) -> Result<(), js::Error> {
+        match &self.isolation {
+            Some(isolation) if self.runs.load(Ordering::SeqCst) > 0 => isolation.record(ctx, names),
+            Some(isolation) => isolation.snapshot(ctx),
+            None => Ok(()),
+        }
+    }
+
+    #[allow(unused_variables)]
+    pub(crate) fn init_globals(&self, builder: &JsEngineBuilder) -> Result<(), Error> {
         let ret: Result<(), js::Error> = self.context.with(|ctx| {
             let global = ctx.globals();
             #[cfg(feature = "console")]
-            {
-                use crate::builtins::{con::Console, Con};
-                global.init_def::<Con>()?;
-                global.set("console", Console)?;
+            if let Some(console) = &self.console {
+                console.init(ctx)?;
+            }
+            #[cfg(feature = "timers")]
+            if let Some(timers) = &self.timers {
+                use crate::builtins::{timers_object, TIMERS_JS};
+                global.set("__timers", timers_object(ctx, timers.clone())?)?;
+                ctx.eval::<(), _>(TIMERS_JS)?;
             }
             #[cfg(feature = "fetch")]
-            {
-                use crate::builtins::Fetch;
-                ctx.globals().init_def::<Fetch>()?;
+            if let Some(fetcher) = &self.fetcher {
+                use crate::builtins::{http_object, FETCH_JS};
+                global.set("__http", http_object(ctx, fetcher.clone())?)?;
+                ctx.eval::<(), _>(FETCH_JS)?;
             }
 
             #[cfg(feature = "dispatcher")]
-            {
+            if let Some(sender) = &self.sender {
                 use crate::builtins::{disp::Dispatcher, Disp};
                 global.init_def::<Disp>()?;
-                global.set("dispatcher", Dispatcher::new(self.sender.clone()))?;
+                global.set("dispatcher", Dispatcher::new(sender.clone()))?;
+                let expose: Function = ctx.eval(crate::builtins::DISPATCHER_JS)?;
+                #[cfg(feature = "builtin_processor")]
+                {
+                    *crate::sync::lock(&self.expose) = Some(js::Persistent::save(ctx, expose));
+                }
+            }
+
+            if let Some(isolation) = &self.isolation {
+                if builder.freeze_builtins {
+                    isolation.freeze(ctx)?;
+                }
+                isolation.snapshot(ctx)?;
             }
             Ok(())
         });
@@ -104,40 +412,130 @@ impl JsEngine {
     }
 }
 
+impl Drop for JsEngine {
+    fn drop(&mut self) {
+        // the persistent values must be released before the runtime
+        let Self {
+            context,
+            isolation,
+            #[cfg(feature = "console")]
+            console,
+            #[cfg(feature = "builtin_processor")]
+            expose,
+            ..
+        } = self;
+        context.with(|ctx| {
+            if let Some(isolation) = isolation {
+                isolation.release(ctx);
+            }
+            #[cfg(feature = "console")]
+            if let Some(console) = console {
+                console.release(ctx);
+            }
+            #[cfg(feature = "builtin_processor")]
+            if let Some(expose) = crate::sync::lock(expose).take() {
+                drop(expose.restore(ctx));
+            }
+        });
+    }
+}
+
 impl fmt::Debug for JsEngine {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("JsEngine").finish()
     }
 }
 
-#[cfg(all(feature = "builtin_processor", feature = "dispatcher"))]
-fn run_processors(
+/// the global holding the function registered at the dotted name, e.g. `crypto` for
+/// `crypto.sign`
+fn root(name: &str) -> String {
+    name.split('.').next().unwrap_or(name).to_owned()
+}
+
+/// Ends the run of a script when dropped, see [`JsEngine::begin_run`].
+#[derive(Debug)]
+pub(crate) struct RunGuard<'a> {
+    engine: &'a JsEngine,
+    /// id of the run in the console, which writes the output of the run
+    #[cfg(feature = "console")]
+    console: Option<u32>,
+}
+
+#[cfg_attr(not(feature = "console"), allow(clippy::unused_self))]
+impl RunGuard<'_> {
+    /// the `console` of the run, passed to the handler function of the script
+    pub(crate) fn console<'js>(&self, ctx: js::Ctx<'js>) -> Result<js::Value<'js>, js::Error> {
+        #[cfg(feature = "console")]
+        if let (Some(console), Some(id)) = (&self.engine.console, self.console) {
+            return console.create(ctx, id);
+        }
+        ctx.globals().get("console")
+    }
+
+    /// capture the console output of the run
+    fn capture(&self) {
+        #[cfg(feature = "console")]
+        if let (Some(console), Some(id)) = (&self.engine.console, self.console) {
+            console.capture(id);
+        }
+    }
+
+    /// the console output captured since [`RunGuard::capture`]
+    fn captured(&self) -> Vec<crate::LogEntry> {
+        #[cfg(feature = "console")]
+        if let (Some(console), Some(id)) = (&self.engine.console, self.console) {
+            return console.captured(id);
+        }
+        Vec::new()
+    }
+}
+
+impl Drop for RunGuard<'_> {
+    fn drop(&mut self) {
+        #[cfg(feature = "console")]
+        if let (Some(console), Some(id)) = (&self.engine.console, self.console) {
+            console.end_run(id);
+        }
+        self.engine.end_run();
+    }
+}
+
+/// removes the interrupt handler of [`JsEngine::run_with_cancellation`] when dropped
+struct InterruptGuard<'a>(&'a JsEngine);
+
+impl Drop for InterruptGuard<'_> {
+    fn drop(&mut self) {
+        self.0.runtime.set_interrupt_handler(None);
+    }
+}
+
+#[cfg(feature = "builtin_processor")]
+pub(crate) fn run_processors(
     rx: flume::Receiver<crate::MsgChannel>,
-    processors: Vec<(&str, &str, Box<dyn crate::Processor>)>,
+    processors: crate::ProcessorRegistry,
 ) {
-    let processors: std::collections::HashMap<(String, String), Box<dyn crate::Processor>> =
-        processors
-            .into_iter()
-            .map(|(ns, name, processor)| ((ns.to_owned(), name.to_owned()), processor))
-            .collect();
-
     tokio::spawn(async move {
         while let Ok(msg) = rx.recv_async().await {
             let name = format!("{}.{}", msg.namespace, msg.name);
             tracing::info!("Received request for {name}: {:#?}", msg.args);
-            let processor = match processors.get(&(msg.namespace, msg.name)) {
-                Some(p) => p,
+            let processor = match processors.get(&msg.namespace, &msg.name) {
+                Some(processor) => processor,
                 None => {
-                    if let Err(e) = msg.res.send(Err(format!("{} not found", name))) {
+                    let err = crate::ProcessorError::new(format!("{} not found", name))
+                        .with_code("not_found");
+                    if let Err(e) = msg.res.send(Err(err)) {
                         tracing::warn!("send error: {:?}", e);
                     };
                     continue;
                 }
             };
-            let ret = processor.call(msg.args).await;
-            if let Err(e) = msg.res.send(ret) {
-                tracing::warn!("send error: {:?}", e);
-            }
+            // process the messages concurrently so that a slow processor won't block others
+            tokio::spawn(async move {
+                let ret = processor.call(msg.args).await;
+                if let Err(e) = msg.res.send(ret) {
+                    tracing::warn!("send error: {:?}", e);
+                }
+            });
         }
     });
 }
@@ -156,6 +554,15 @@ mod tests {
         Ok(args)
     }
 
+    #[cfg(feature = "builtin_processor")]
+    fn auth_revoke_token(
+        _args: JsonValue,
+    ) -> std::result::Result<JsonValue, crate::ProcessorError> {
+        Err(crate::ProcessorError::new("token expired")
+            .with_code("expired")
+            .with_details(JsonValue::object(json!({"id": 1}))))
+    }
+
     #[cfg(feature = "builtin_processor")]
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn js_engine_should_work() -> Result<()> {
@@ -184,18 +591,1483 @@ mod tests {
         Ok(())
     }
 
-    #[cfg(feature = "fetch")]
-    #[cfg(not(feature = "dispatcher"))]
+    #[cfg(feature = "builtin_processor")]
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-    async fn fetch_should_work() {
-        let engine = JsEngine::create().expect("valid");
+    async fn dispatches_should_be_awaited_concurrently() -> Result<()> {
+        let engine = JsEngine::create_with_processors(vec![(
+            "auth",
+            "create_token",
+            Box::new(auth_create_token) as Box<dyn crate::Processor>,
+        )])?;
+        let ret = engine
+            .run(
+                r#"
+                return await Promise.all([
+                    dispatcher.dispatch('auth', 'create_token', {a: 1}),
+                    dispatcher.dispatch('auth', 'create_token', {a: 2}),
+                ]);
+                "#,
+                JsonValue::null(),
+            )
+            .await?;
+
+        assert_eq!(ret.0, json!([{"a": 1}, {"a": 2}]));
+        Ok(())
+    }
+
+    #[cfg(feature = "builtin_processor")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn processor_errors_should_be_thrown_in_js() -> Result<()> {
+        let engine = JsEngine::create_with_processors(vec![(
+            "auth",
+            "revoke_token",
+            Box::new(auth_revoke_token) as Box<dyn crate::Processor>,
+        )])?;
+        let ret = engine
+            .run(
+                r#"
+                try {
+                    await dispatcher.dispatch('auth', 'revoke_token', {id: 1});
+                } catch (e) {
+                    return [e instanceof DispatchError, e.namespace, e.method, e.code, e.message, e.details];
+                }
+                "#,
+                JsonValue::null(),
+            )
+            .await?;
+        assert_eq!(
+            ret.0,
+            json!([true, "auth", "revoke_token", "expired", "token expired", {"id": 1}])
+        );
+
+        let ret = engine
+            .run(
+                "return dispatcher.dispatch('auth', 'unknown', {})",
+                JsonValue::null(),
+            )
+            .await;
+        let exception = match ret {
+            Err(Error::JsException { exception }) => exception,
+            v => panic!("unexpected result: {:?}", v),
+        };
+        assert_eq!(exception.name, "DispatchError");
+        assert_eq!(exception.message, "auth.unknown not found");
+        Ok(())
+    }
+
+    #[cfg(feature = "builtin_processor")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn typed_processors_should_be_registered_at_runtime() -> Result<()> {
+        #[derive(serde::Deserialize)]
+        struct AddArgs {
+            a: i64,
+            b: i64,
+        }
+
+        let registry = crate::ProcessorRegistry::new();
+        let engine = JsEngine::create_with_registry(registry.clone())?;
+        registry.register_fn("math", "add", |args: AddArgs| async move {
+            Ok::<_, String>(args.a + args.b)
+        });
+
+        let ret = engine
+            .run(
+                r#"
+                const sum = await dispatcher.dispatch('math', 'add', {a: 1, b: 2});
+                try {
+                    await dispatcher.dispatch('math', 'add', {a: 1});
+                } catch (e) {
+                    return [sum, e.code];
+                }
+                "#,
+                JsonValue::null(),
+            )
+            .await?;
+        assert_eq!(ret.0, json!([3, "invalid_args"]));
+
+        assert!(registry.remove("math", "add"));
+        let ret = engine
+            .run(
+                "return dispatcher.dispatch('math', 'add', {a: 1, b: 2}).catch(e => e.code)",
+                JsonValue::null(),
+            )
+            .await?;
+        assert_eq!(ret.0, json!("not_found"));
+        Ok(())
+    }
+
+    #[cfg(feature = "builtin_processor")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn processors_should_be_exposed_as_namespaces() -> Result<()> {
+        let engine = JsEngine::create_with_processors(vec![(
+            "auth",
+            "create_token",
+            Box::new(auth_create_token) as Box<dyn crate::Processor>,
+        )])?;
+        let ret = engine
+            .run(
+                r#"
+                const token = await auth.create_token({a: 1});
+                return [token, dispatcher.namespaces(), dispatcher.methods('auth')];
+                "#,
+                JsonValue::null(),
+            )
+            .await?;
+        assert_eq!(ret.0, json!([{"a": 1}, ["auth"], ["create_token"]]));
+
+        let registry = engine.processors().expect("registry");
+        registry.register("auth", "revoke_token", auth_revoke_token);
+        registry.register("console", "log", auth_create_token);
+        match engine.expose_processors() {
+            Err(Error::NamespaceConflict { namespaces }) => assert_eq!(namespaces, ["console"]),
+            v => panic!("unexpected result: {:?}", v),
+        }
+        let ret = engine
+            .run(
+                r#"
+                return [
+                    dispatcher.namespaces(),
+                    dispatcher.methods('auth'),
+                    typeof console.log,
+                    typeof auth.revoke_token,
+                    typeof dispatcher.expose,
+                ];
+                "#,
+                JsonValue::null(),
+            )
+            .await?;
+        assert_eq!(
+            ret.0,
+            json!([
+                ["auth"],
+                ["create_token", "revoke_token"],
+                "function",
+                "function",
+                "undefined"
+            ])
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "builtin_processor")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn frozen_builtins_should_include_the_dispatcher() -> Result<()> {
+        let registry: crate::ProcessorRegistry = vec![(
+            "auth",
+            "create_token",
+            Box::new(auth_create_token) as Box<dyn crate::Processor>,
+        )]
+        .into_iter()
+        .collect();
+        let engine = JsEngine::builder()
+            .processors(registry)
+            .freeze_builtins(true)
+            .build()?;
+        let code = r#"
+            const fake = async () => 'fake';
+            const attempts = [
+                () => { dispatcher.dispatch = fake; },
+                () => { Object.getPrototypeOf(dispatcher).send = fake; },
+                () => { auth.create_token = fake; },
+                () => { globalThis.auth = { create_token: fake }; },
+                () => { delete globalThis.auth; },
+            ];
+            const failed = attempts.map((attempt) => {
+                try {
+                    attempt();
+                    return false;
+                } catch (e) {
+                    return e instanceof TypeError;
+                }
+            });
+            return [...failed, await auth.create_token({a: 1})];
+        "#;
+        let ret = engine.run(code, JsonValue::null()).await?;
+        assert_eq!(ret.0, json!([true, true, true, true, true, {"a": 1}]));
+
+        // the namespaces are still updated by the host
+        let registry = engine.processors().expect("registry");
+        registry.register("auth", "revoke_token", auth_revoke_token);
+        registry.register("user", "create", auth_create_token);
+        engine.expose_processors()?;
+        let code = "return [dispatcher.methods('auth'), typeof user.create];";
+        let ret = engine.run(code, JsonValue::null()).await?;
+        assert_eq!(ret.0, json!([["create_token", "revoke_token"], "function"]));
+        assert!(registry.remove("user", "create"));
+        engine.expose_processors()?;
+        let ret = engine.run("return typeof user;", JsonValue::null()).await?;
+        assert_eq!(ret.0, json!("undefined"));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn js_engine_builder_should_apply_limits() {
+        let engine = JsEngine::builder()
+            .memory_limit(16 * 1024 * 1024)
+            .max_stack_size(512 * 1024)
+            .gc_threshold(1024 * 1024)
+            .build()
+            .expect("valid");
         let ret = engine
             .run(
-                "return await fetch('https://httpbin.org/get');",
+                "return new Array(256 * 1024).fill(1).length;",
                 JsonValue::null(),
             )
             .await
             .expect("valid");
-        assert_eq!(ret.0["url"], "https://httpbin.org/get");
+        assert_eq!(ret.0, json!(256 * 1024));
+
+        let engine = JsEngine::builder()
+            .memory_limit(1024 * 1024)
+            .build()
+            .expect("valid");
+        let ret = engine
+            .run(
+                "return new Array(256 * 1024).fill(1).length;",
+                JsonValue::null(),
+            )
+            .await;
+        assert!(ret.is_err());
+    }
+
+    #[cfg(feature = "console")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn console_output_should_be_captured_per_run() {
+        use crate::LogLevel;
+
+        let engine = JsEngine::builder().build().expect("valid");
+        let code = r#"
+            console.log('hello', req.name, { a: 1 });
+            console.warn('careful');
+            console.error('oops', [1, 2]);
+            return 42;
+        "#;
+        let output = engine
+            .run_captured(code, JsonValue(json!({ "name": "alice" })))
+            .await
+            .expect("valid");
+        assert_eq!(output.value.0, json!(42));
+        let logs: Vec<_> = output
+            .logs
+            .iter()
+            .map(|log| (log.level, log.message.as_str()))
+            .collect();
+        assert_eq!(
+            logs,
+            vec![
+                (LogLevel::Log, "hello alice {\n  \"a\": 1\n}"),
+                (LogLevel::Warn, "careful"),
+                (LogLevel::Error, "oops [1, 2]"),
+            ]
+        );
+        assert_eq!(
+            output.logs[0].args,
+            vec![
+                JsonValue(json!("hello")),
+                JsonValue(json!("alice")),
+                JsonValue(json!({ "a": 1 })),
+            ]
+        );
+        assert!(output.logs[0].timestamp <= output.logs[2].timestamp);
+
+        // runs without capture do not collect the output
+        engine
+            .run("console.log('not captured');", JsonValue::null())
+            .await
+            .expect("valid");
+        let output = engine
+            .run_captured("return 1;", JsonValue::null())
+            .await
+            .expect("valid");
+        assert!(output.logs.is_empty());
+    }
+
+    #[cfg(all(feature = "console", feature = "timers"))]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn overlapping_captures_should_keep_their_own_output() {
+        let engine = JsEngine::builder().build().expect("valid");
+        let slow = engine.run_captured(
+            r#"
+            console.log('slow start');
+            await new Promise((resolve) => setTimeout(resolve, 100));
+            console.log('slow end');
+            "#,
+            JsonValue::null(),
+        );
+        let fast = engine.run_captured("console.log('fast');", JsonValue::null());
+        let (slow, fast) = tokio::join!(slow, fast);
+        let messages = |logs: &[crate::LogEntry]| {
+            logs.iter()
+                .map(|log| log.message.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages(&slow.expect("valid").logs),
+            vec!["slow start", "slow end"]
+        );
+        assert_eq!(messages(&fast.expect("valid").logs), vec!["fast"]);
+    }
+
+    #[cfg(feature = "console")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn console_sink_should_receive_the_output() {
+        use crate::{LogLevel, MemorySink};
+
+        let sink = MemorySink::new();
+        let engine = JsEngine::builder()
+            .console_sink(sink.clone())
+            .build()
+            .expect("valid");
+        engine
+            .run("console.info('from run', 1);", JsonValue::null())
+            .await
+            .expect("valid");
+        let script = engine
+            .compile("greet.js", "console.warn(`hi ${req}`);")
+            .expect("valid");
+        engine
+            .run_compiled(&script, JsonValue(json!("bob")))
+            .await
+            .expect("valid");
+
+        let entries: Vec<_> = sink
+            .take()
+            .into_iter()
+            .map(|(script, entry)| (script, entry.level, entry.message))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("script".to_owned(), LogLevel::Info, "from run 1".to_owned()),
+                ("greet.js".to_owned(), LogLevel::Warn, "hi bob".to_owned()),
+            ]
+        );
+        assert!(sink.entries().is_empty());
+    }
+
+    #[cfg(all(feature = "console", feature = "timers"))]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn overlapping_runs_should_name_their_own_script() {
+        use crate::MemorySink;
+
+        let sink = MemorySink::new();
+        let engine = JsEngine::builder()
+            .console_sink(sink.clone())
+            .build()
+            .expect("valid");
+        let slow = engine
+            .compile(
+                "slow.js",
+                r#"
+                console.log('slow start');
+                await new Promise((resolve) => setTimeout(resolve, 100));
+                console.log('slow end');
+                "#,
+            )
+            .expect("valid");
+        let fast = engine
+            .compile("fast.js", "console.log('fast');")
+            .expect("valid");
+        let (slow, fast) = tokio::join!(
+            engine.run_compiled(&slow, JsonValue::null()),
+            engine.run_compiled(&fast, JsonValue::null()),
+        );
+        slow.expect("valid");
+        fast.expect("valid");
+
+        let entries: Vec<_> = sink
+            .take()
+            .into_iter()
+            .map(|(script, entry)| (script, entry.message))
+            .collect();
+        let entry = |script: &str, message: &str| (script.to_owned(), message.to_owned());
+        assert_eq!(
+            entries,
+            vec![
+                entry("slow.js", "slow start"),
+                entry("fast.js", "fast"),
+                entry("slow.js", "slow end"),
+            ]
+        );
+    }
+
+    #[cfg(feature = "console")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn console_api_should_be_complete() {
+        use crate::LogLevel;
+
+        let engine = JsEngine::builder().build().expect("valid");
+        let code = r#"
+            console.info('%s has %d items', 'cart', 3);
+            console.debug('debug');
+            console.assert(1 + 1 === 2, 'not logged');
+            console.assert(false, 'expected %s', 'ok');
+            console.count();
+            console.count('x');
+            console.count();
+            console.group('outer');
+            console.log('inner');
+            console.groupEnd();
+            console.time('t');
+            console.timeEnd('t');
+            console.timeEnd('t');
+            console.table([{ a: 1, b: 'x' }, { a: 2 }]);
+            console.trace('here');
+        "#;
+        let output = engine
+            .run_captured(code, JsonValue::null())
+            .await
+            .expect("valid");
+        let logs: Vec<_> = output
+            .logs
+            .iter()
+            .map(|log| (log.level, log.message.as_str()))
+            .collect();
+        assert_eq!(
+            logs[..8],
+            [
+                (LogLevel::Info, "cart has 3 items"),
+                (LogLevel::Debug, "debug"),
+                (LogLevel::Error, "Assertion failed: expected ok"),
+                (LogLevel::Log, "default: 1"),
+                (LogLevel::Log, "x: 1"),
+                (LogLevel::Log, "default: 2"),
+                (LogLevel::Log, "outer"),
+                (LogLevel::Log, "  inner"),
+            ]
+        );
+        assert_eq!(logs[8].0, LogLevel::Log);
+        assert!(logs[8].1.starts_with("t: ") && logs[8].1.ends_with("ms"));
+        assert_eq!(logs[9], (LogLevel::Warn, "Timer 't' does not exist"));
+        assert_eq!(
+            logs[10],
+            (
+                LogLevel::Log,
+                [
+                    "┌─────────┬───┬─────┐",
+                    "│ (index) │ a │  b  │",
+                    "├─────────┼───┼─────┤",
+                    "│    0    │ 1 │ 'x' │",
+                    "│    1    │ 2 │     │",
+                    "└─────────┴───┴─────┘",
+                ]
+                .join("\n")
+                .as_str()
+            )
+        );
+        assert_eq!(logs[11].0, LogLevel::Trace);
+        assert_eq!(logs[11].1, "Trace: here\n    at default (script:16)");
+    }
+
+    #[cfg(feature = "console")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn console_state_should_be_reset_per_run() {
+        let engine = JsEngine::builder().build().expect("valid");
+        let code = r#"
+            console.count();
+            console.time('t');
+            console.group('outer');
+            console.log('inner');
+        "#;
+        for _ in 0..2 {
+            let output = engine
+                .run_captured(code, JsonValue::null())
+                .await
+                .expect("valid");
+            let messages: Vec<_> = output.logs.iter().map(|log| log.message.as_str()).collect();
+            assert_eq!(messages, vec!["default: 1", "outer", "  inner"]);
+        }
+    }
+
+    #[cfg(all(feature = "console", feature = "timers"))]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn overlapping_runs_should_keep_their_own_console_state() {
+        let engine = JsEngine::builder().build().expect("valid");
+        let slow = engine.run_captured(
+            r#"
+            console.count();
+            console.group('slow');
+            await new Promise((resolve) => setTimeout(resolve, 100));
+            console.count();
+            "#,
+            JsonValue::null(),
+        );
+        let fast = engine.run_captured("console.count();", JsonValue::null());
+        let (slow, fast) = tokio::join!(slow, fast);
+        let messages = |logs: &[crate::LogEntry]| {
+            logs.iter()
+                .map(|log| log.message.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages(&slow.expect("valid").logs),
+            vec!["default: 1", "slow", "  default: 2"]
+        );
+        assert_eq!(messages(&fast.expect("valid").logs), vec!["default: 1"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn scripts_should_import_modules_from_the_resolver() {
+        let resolver = crate::MemoryResolver::new()
+            .with_module(
+                "lib/crypto",
+                "import { hash } from './hash'; export const sign = (s) => `signed:${hash(s)}`;",
+            )
+            .with_module("lib/hash", "export const hash = (s) => s.length;");
+        let engine = JsEngine::builder()
+            .module_resolver(resolver.clone())
+            .build()
+            .expect("valid");
+        let code = r#"
+            import { sign } from "lib/crypto";
+            const { hash } = await import("lib/hash");
+            return [sign(req), hash(req)];
+        "#;
+        let ret = engine.run(code, JsonValue::object(json!("abc"))).await;
+        assert_eq!(ret.expect("valid").0, json!(["signed:3", 3]));
+
+        let script = engine.compile("lib/main", code).expect("valid");
+        let ret = engine
+            .run_compiled(&script, JsonValue::object(json!("ab")))
+            .await;
+        assert_eq!(ret.expect("valid").0, json!(["signed:2", 2]));
+
+        resolver.insert("lib/escape", "import '../../secret';");
+        for code in ["import 'lib/missing';", "import 'lib/escape';"] {
+            let ret = engine.run(code, JsonValue::null()).await;
+            assert!(ret.is_err(), "{} should fail", code);
+        }
+
+        // importing fails without a resolver
+        let engine = JsEngine::builder().build().expect("valid");
+        let ret = engine.run(code, JsonValue::object(json!("abc"))).await;
+        assert!(ret.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn modules_should_be_read_from_files_or_embedded() {
+        let dir = std::env::temp_dir().join(format!("easy-qjs-modules-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).expect("valid");
+        std::fs::write(
+            dir.join("lib/math.js"),
+            "export const add = (a, b) => a + b;",
+        )
+        .expect("valid");
+        let engine = JsEngine::builder()
+            .module_resolver(crate::DirectoryResolver::new(&dir))
+            .build()
+            .expect("valid");
+        let code = "import { add } from 'lib/math'; return add(1, 2);";
+        let ret = engine.run(code, JsonValue::null()).await;
+        std::fs::remove_dir_all(&dir).expect("valid");
+        assert_eq!(ret.expect("valid").0, json!(3));
+
+        static MODULES: &[(&str, &str)] = &[("lib/math", "export const sub = (a, b) => a - b;")];
+        let engine = JsEngine::builder()
+            .module_resolver(crate::EmbeddedResolver::new(MODULES))
+            .build()
+            .expect("valid");
+        let code = "import { sub } from 'lib/math'; return sub(1, 2);";
+        let ret = engine.run(code, JsonValue::null()).await;
+        assert_eq!(ret.expect("valid").0, json!(-1));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn thrown_exception_should_be_reported_with_details() {
+        let engine = JsEngine::builder().build().expect("valid");
+        let ret = engine
+            .run(
+                "let a = 1;\nthrow new TypeError(`bad value ${a}`);",
+                JsonValue::null(),
+            )
+            .await;
+        let exception = match ret {
+            Err(Error::JsException { exception }) => exception,
+            v => panic!("unexpected result: {:?}", v),
+        };
+        assert_eq!(exception.name, "TypeError");
+        assert_eq!(exception.message, "bad value 1");
+        assert_eq!(exception.file.as_deref(), Some("script"));
+        assert_eq!(exception.line, Some(2));
+        assert!(exception.stack.is_some());
+
+        let ret = engine.run("throw 'oops';", JsonValue::null()).await;
+        let exception = match ret {
+            Err(Error::JsException { exception }) => exception,
+            v => panic!("unexpected result: {:?}", v),
+        };
+        assert_eq!(exception.name, "Error");
+        assert_eq!(exception.message, "oops");
+
+        // the synchronous exceptions keep their names too
+        let ret = engine.run("let = ;", JsonValue::null()).await;
+        let exception = match ret {
+            Err(Error::JsException { exception }) => exception,
+            v => panic!("unexpected result: {:?}", v),
+        };
+        assert_eq!(exception.name, "SyntaxError");
+        assert_eq!(exception.file.as_deref(), Some("script"));
+        engine
+            .load_global_js("check", "export default { check() { return null.x; } };")
+            .expect("valid");
+        let ret = engine.call_function("check", vec![]).await;
+        let exception = match ret {
+            Err(Error::JsException { exception }) => exception,
+            v => panic!("unexpected result: {:?}", v),
+        };
+        assert_eq!(exception.name, "TypeError");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn globals_should_be_accessed_from_the_host() {
+        let engine = JsEngine::builder().build().expect("valid");
+        engine
+            .load_global_js(
+                "helpers",
+                r#"export default {
+                    offset: (n) => n + config.offset,
+                    later: async (a, b) => `${a}-${b}`,
+                    count() { return this.counter = (this.counter ?? 0) + 1; },
+                };"#,
+            )
+            .expect("valid");
+        engine
+            .set_global("config", JsonValue::object(json!({"offset": 10})))
+            .expect("valid");
+        let config = engine.get_global("config").expect("valid");
+        assert_eq!(config.map(|v| v.0), Some(json!({"offset": 10})));
+
+        let args = vec![JsonValue::object(json!(1))];
+        let ret = engine.call_function("offset", args).await.expect("valid");
+        assert_eq!(ret.0, json!(11));
+        let args = vec![JsonValue::object(json!("a")), JsonValue::object(json!(2))];
+        let ret = engine.call_function("later", args).await.expect("valid");
+        assert_eq!(ret.0, json!("a-2"));
+        let ret = engine.call_function("count", vec![]).await.expect("valid");
+        assert_eq!(ret.0, json!(1));
+
+        // the globals are shared with the runs
+        let ret = engine.run("return [config.offset, counter];", JsonValue::null());
+        assert_eq!(ret.await.expect("valid").0, json!([10, 1]));
+
+        engine.remove_global("config").expect("valid");
+        assert_eq!(engine.get_global("config").expect("valid"), None);
+        engine.remove_global("config").expect("valid");
+        let args = vec![JsonValue::object(json!(1))];
+        assert!(engine.call_function("offset", args).await.is_err());
+        assert!(engine.call_function("missing", vec![]).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn registered_functions_should_be_called() {
+        let engine = JsEngine::builder().build().expect("valid");
+        engine
+            .register_fn("sum", |args: JsonValue| {
+                let args: Vec<f64> = serde_json::from_value(args.0).map_err(|e| e.to_string())?;
+                Ok::<_, String>(JsonValue::object(json!(args.iter().sum::<f64>())))
+            })
+            .expect("valid");
+        engine
+            .register_async_fn("crypto.sign", |args: JsonValue| async move {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+                match args.0[0].as_str() {
+                    Some(data) => Ok(JsonValue::object(json!(format!("signed:{}", data)))),
+                    None => Err(crate::ProcessorError::new("data is required")
+                        .with_code("invalid")
+                        .with_details(JsonValue::object(json!({"args": args.0})))),
+                }
+            })
+            .expect("valid");
+        engine
+            .register_fn("crypto.hash", |args: JsonValue| {
+                Ok::<_, String>(JsonValue::object(json!(args.0.to_string().len())))
+            })
+            .expect("valid");
+
+        let code = r#"
+            let error;
+            try {
+                await crypto.sign(1);
+            } catch (e) {
+                error = { message: e.message, code: e.code, details: e.details, fn: e.function };
+            }
+            let syncError;
+            try {
+                sum("a");
+            } catch (e) {
+                syncError = e instanceof Error;
+            }
+            return [sum(1, 2, 3), await crypto.sign("abc"), crypto.hash(), error, syncError];
+        "#;
+        let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+        assert_eq!(
+            ret.0,
+            json!([
+                6,
+                "signed:abc",
+                2,
+                {
+                    "message": "data is required",
+                    "code": "invalid",
+                    "details": { "args": [1] },
+                    "fn": "crypto.sign",
+                },
+                true,
+            ])
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn globals_should_be_restored_after_each_run() {
+        let engine = JsEngine::builder()
+            .isolate_globals(true)
+            .build()
+            .expect("valid");
+        engine
+            .load_global_js("helpers", "export default { double: (n) => n * 2 };")
+            .expect("valid");
+        engine
+            .set_global("config", JsonValue::object(json!({"limit": 1})))
+            .expect("valid");
+        let code = r#"
+            const seen = [typeof leaked, config.limit, double(2), typeof JSON.parse];
+            globalThis.leaked = true;
+            config = { limit: 2 };
+            delete globalThis.double;
+            globalThis.JSON = null;
+            return seen;
+        "#;
+        for _ in 0..2 {
+            let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+            assert_eq!(ret.0, json!(["undefined", 1, 4, "function"]));
+        }
+        assert_eq!(engine.get_global("leaked").expect("valid"), None);
+
+        // the globals set by the host are kept
+        engine
+            .set_global("config", JsonValue::object(json!({"limit": 3})))
+            .expect("valid");
+        let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+        assert_eq!(ret.0, json!(["undefined", 3, 4, "function"]));
+
+        // but not the globals a script makes non-configurable, which are reported
+        let code = "Object.defineProperty(globalThis, 'pinned', { value: 1 });";
+        engine.run(code, JsonValue::null()).await.expect("valid");
+        let isolation = engine.isolation.as_ref().expect("isolation");
+        let kept = engine.context.with(|ctx| isolation.restore(ctx));
+        assert_eq!(kept.expect("valid"), vec!["pinned"]);
+    }
+
+    #[cfg(feature = "timers")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn host_changes_during_a_run_should_not_keep_its_globals() {
+        let engine = JsEngine::builder()
+            .isolate_globals(true)
+            .build()
+            .expect("valid");
+        let run = engine.run(
+            r#"
+            globalThis.leaked = true;
+            await new Promise((resolve) => setTimeout(resolve, 100));
+            "#,
+            JsonValue::null(),
+        );
+        let host = async {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            engine
+                .set_global("config", JsonValue::object(json!(1)))
+                .expect("valid");
+            engine
+                .register_fn("host.echo", |args: JsonValue| Ok::<_, String>(args))
+                .expect("valid");
+        };
+        let (ret, ()) = tokio::join!(run, host);
+        ret.expect("valid");
+
+        let code = "return [typeof leaked, config, host.echo(1)];";
+        let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+        assert_eq!(ret.0, json!(["undefined", 1, [1]]));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn builtins_should_be_frozen() {
+        let engine = JsEngine::builder()
+            .freeze_builtins(true)
+            .build()
+            .expect("valid");
+        let code = r#"
+            const attempts = [
+                () => { Object.keys = null; },
+                () => { globalThis.JSON = null; },
+                () => { Math.max = Math.min; },
+                () => { delete globalThis.Promise; },
+            ];
+            const failed = attempts.map((attempt) => {
+                try {
+                    attempt();
+                    return false;
+                } catch (e) {
+                    return e instanceof TypeError;
+                }
+            });
+            globalThis.own = 1;
+            return [...failed, typeof Object.keys, Math.max(1, 2)];
+        "#;
+        let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+        assert_eq!(ret.0, json!([true, true, true, true, "function", 2]));
+
+        // without the isolation, the other globals are still shared between the runs
+        let ret = engine.run("return own;", JsonValue::null()).await;
+        assert_eq!(ret.expect("valid").0, json!(1));
+        engine
+            .register_fn("host.echo", |args: JsonValue| Ok::<_, String>(args))
+            .expect("valid");
+        let ret = engine.run("return host.echo(1);", JsonValue::null()).await;
+        assert_eq!(ret.expect("valid").0, json!([1]));
+
+        // but not under a frozen namespace or a primitive
+        let echo = |args: JsonValue| Ok::<_, String>(args);
+        assert!(engine.register_fn("Math.echo", echo).is_err());
+        assert!(engine.register_fn("own.echo", echo).is_err());
+        assert!(engine.register_fn("JSON.codec.echo", echo).is_err());
+        let code = "return [typeof Math.echo, typeof own.echo, typeof JSON.codec];";
+        let ret = engine.run(code, JsonValue::null()).await;
+        assert_eq!(
+            ret.expect("valid").0,
+            json!(["undefined", "undefined", "undefined"])
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn run_module_should_call_the_named_export() {
+        let resolver = crate::MemoryResolver::new().with_module(
+            "lib/greet",
+            "export const greet = (name) => `hello ${name}`;",
+        );
+        let engine = JsEngine::builder()
+            .module_resolver(resolver)
+            .build()
+            .expect("valid");
+        let source = r#"
+            import { greet } from "lib/greet";
+
+            const twice = (s) => `${s}, ${s}`;
+
+            export async function onRequest(req, times) {
+                return { message: twice(greet(req.name)), times };
+            }
+
+            export async function fail() {
+                throw new RangeError("out of range");
+            }
+
+            export const version = 1;
+        "#;
+        let args = vec![
+            JsonValue::object(json!({"name": "bob"})),
+            JsonValue::object(json!(2)),
+        ];
+        let ret = engine.run_module(source, "onRequest", args).await;
+        assert_eq!(
+            ret.expect("valid").0,
+            json!({"message": "hello bob, hello bob", "times": 2})
+        );
+
+        // the line numbers are those of the source
+        let exception = match engine.run_module(source, "fail", vec![]).await {
+            Err(Error::JsException { exception }) => exception,
+            v => panic!("unexpected result: {:?}", v),
+        };
+        assert_eq!(exception.name, "RangeError");
+        assert_eq!(exception.file.as_deref(), Some("module"));
+        assert_eq!(exception.line, Some(11));
+
+        for entry in ["version", "missing"] {
+            let ret = engine.run_module(source, entry, vec![]).await;
+            assert!(ret.is_err(), "{} should not be called", entry);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn run_with_cancellation_should_interrupt_infinite_loop() {
+        let engine = JsEngine::builder().build().expect("valid");
+        let cancellation = Cancellation::with_timeout(std::time::Duration::from_millis(50));
+        let ret = engine
+            .run_with_cancellation("while (true) {}", JsonValue::null(), cancellation)
+            .await;
+        assert!(matches!(ret, Err(Error::Timeout)));
+
+        let cancellation = Cancellation::default();
+        cancellation.cancel();
+        let ret = engine
+            .run_with_cancellation(
+                "await new Promise(() => {}); return 1;",
+                JsonValue::null(),
+                cancellation,
+            )
+            .await;
+        assert!(matches!(ret, Err(Error::Cancelled)));
+
+        // the engine is still usable after the interruption
+        let ret = engine
+            .run_with_cancellation("return 1;", JsonValue::null(), Cancellation::default())
+            .await
+            .expect("valid");
+        assert_eq!(ret.0, json!(1));
+    }
+
+    #[cfg(feature = "timers")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn dropped_cancellable_run_should_remove_the_interrupt_handler() {
+        use std::time::Duration;
+
+        let engine = JsEngine::builder().build().expect("valid");
+        let cancellation = Cancellation::default();
+        let run = engine.run_with_cancellation(
+            "await new Promise((resolve) => setTimeout(resolve, 5000));",
+            JsonValue::null(),
+            cancellation.clone(),
+        );
+        assert!(tokio::time::timeout(Duration::from_millis(50), run)
+            .await
+            .is_err());
+
+        // the cancellation of the dropped run no longer interrupts the others
+        cancellation.cancel();
+        let code = "let sum = 0; for (let i = 0; i < 100000; i++) { sum += i; } return sum;";
+        let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+        assert_eq!(ret.0, json!(4999950000.0));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn huge_sparse_arrays_should_be_rejected_before_allocation() {
+        let engine = JsEngine::builder().build().expect("valid");
+        let mut codes = vec![
+            "return new Array(2 ** 30);",
+            "return new Array(2 ** 32 - 1);",
+        ];
+        if cfg!(feature = "console") {
+            codes.push("console.log(new Array(2 ** 30));");
+        }
+        for code in codes {
+            let ret = engine.run(code, JsonValue::null()).await;
+            let message = ret.expect_err("too large").to_string();
+            assert!(
+                message.contains("more than 1000000 elements"),
+                "{}",
+                message
+            );
+        }
+        let ret = engine
+            .run_typed::<_, Vec<u8>>("return new Array(2 ** 32 - 1);", &())
+            .await;
+        let message = ret.expect_err("too large").to_string();
+        assert!(
+            message.contains("more than 1000000 elements"),
+            "{}",
+            message
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn run_typed_should_convert_the_request_and_result() {
+        use serde::Deserialize;
+        use std::collections::HashMap;
+
+        #[derive(Debug, Serialize)]
+        struct Order {
+            id: u64,
+            items: Vec<(String, u32)>,
+            coupon: Option<String>,
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum Status {
+            Accepted { total: u32 },
+            Rejected(String),
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Receipt {
+            id: u64,
+            status: Status,
+            counts: HashMap<String, u32>,
+            note: Option<String>,
+        }
+
+        let engine = JsEngine::builder()
+            .json_options(crate::JsonOptions::lossless())
+            .build()
+            .expect("valid");
+        let code = r#"
+            const total = req.items.reduce((sum, [, n]) => sum + n, 0);
+            const counts = Object.fromEntries(req.items);
+            const status = req.coupon === null
+                ? { Accepted: { total } }
+                : { Rejected: `invalid coupon ${req.coupon}` };
+            return { id: req.id + 1n, status, counts };
+        "#;
+        let order = Order {
+            id: u64::MAX - 1,
+            items: vec![("apple".into(), 2), ("pear".into(), 3)],
+            coupon: None,
+        };
+        let receipt: Receipt = engine.run_typed(code, &order).await.expect("valid");
+        assert_eq!(
+            receipt,
+            Receipt {
+                id: u64::MAX,
+                status: Status::Accepted { total: 5 },
+                counts: [("apple".into(), 2), ("pear".into(), 3)].into(),
+                note: None,
+            }
+        );
+
+        let order = Order {
+            coupon: Some("FREE".into()),
+            ..order
+        };
+        let receipt: Receipt = engine.run_typed(code, &order).await.expect("valid");
+        assert_eq!(
+            receipt.status,
+            Status::Rejected("invalid coupon FREE".into())
+        );
+
+        // a result of the wrong shape is reported as an error
+        let ret = engine.run_typed::<_, Receipt>("return 1;", &()).await;
+        assert!(ret.is_err());
+    }
+
+    #[cfg(feature = "timers")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn timers_should_work() {
+        let engine = JsEngine::builder().build().expect("valid");
+        let code = r#"
+            const events = [];
+            const start = Date.now();
+            setTimeout((a, b) => events.push(`timeout ${a} ${b}`), 100, 1, 2);
+            const cleared = setTimeout(() => events.push('cleared'), 10);
+            clearTimeout(cleared);
+            queueMicrotask(() => events.push('microtask'));
+            let ticks = 0;
+            const interval = setInterval(() => {
+                ticks += 1;
+                events.push(`tick ${ticks}`);
+                if (ticks === 3) {
+                    clearInterval(interval);
+                }
+            }, 1);
+            await new Promise((resolve) => setTimeout(resolve, 150));
+            return { events, elapsed: Date.now() - start >= 150 };
+        "#;
+        let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+        assert_eq!(
+            ret.0,
+            json!({
+                "events": ["microtask", "tick 1", "tick 2", "tick 3", "timeout 1 2"],
+                "elapsed": true,
+            })
+        );
+    }
+
+    #[cfg(feature = "timers")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn timers_should_be_cancelled_when_the_run_ends() {
+        use std::time::{Duration, Instant};
+
+        let engine = JsEngine::builder().build().expect("valid");
+        let code = r#"
+            globalThis.fired = [];
+            setTimeout(() => fired.push('timeout'), 20);
+            setInterval(() => fired.push('interval'), 5);
+            return 1;
+        "#;
+        engine.run(code, JsonValue::null()).await.expect("valid");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let ret = engine
+            .run("return fired;", JsonValue::null())
+            .await
+            .expect("valid");
+        assert_eq!(ret.0, json!([]));
+
+        // waiting for a timer counts toward the deadline
+        let start = Instant::now();
+        let cancellation = Cancellation::with_timeout(Duration::from_millis(50));
+        let code = r#"
+            setTimeout(() => fired.push('late'), 100);
+            await new Promise((resolve) => setTimeout(resolve, 5000));
+        "#;
+        let ret = engine
+            .run_with_cancellation(code, JsonValue::null(), cancellation)
+            .await;
+        assert!(matches!(ret, Err(Error::Timeout)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let ret = engine
+            .run("return fired;", JsonValue::null())
+            .await
+            .expect("valid");
+        assert_eq!(ret.0, json!([]));
+    }
+
+    #[cfg(feature = "timers")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn timers_should_survive_overlapping_runs() {
+        let engine = JsEngine::builder().build().expect("valid");
+        let slow = engine.run(
+            "await new Promise((resolve) => setTimeout(resolve, 200)); return 'slow';",
+            JsonValue::null(),
+        );
+        let fast = engine.run("return 'fast';", JsonValue::null());
+        let (slow, fast) = tokio::join!(slow, fast);
+        assert_eq!(fast.expect("valid").0, json!("fast"));
+        assert_eq!(slow.expect("valid").0, json!("slow"));
+    }
+
+    #[cfg(feature = "fetch")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn fetch_should_work() {
+        use crate::{FetchResponse, MockFetchBackend};
+
+        let url = "https://httpbin.org/get";
+        let mock = MockFetchBackend::new().route(
+            "GET",
+            url,
+            FetchResponse::json(200, &json!({ "url": url })),
+        );
+        let engine = JsEngine::builder()
+            .fetch_backend(mock)
+            .build()
+            .expect("valid");
+        let ret = engine
+            .run(
+                "const res = await fetch('https://httpbin.org/get'); return await res.json();",
+                JsonValue::null(),
+            )
+            .await
+            .expect("valid");
+        assert_eq!(ret.0["url"], "https://httpbin.org/get");
+    }
+
+    #[cfg(feature = "fetch")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn fetch_backend_should_receive_requests() {
+        use crate::{FetchPolicy, FetchResponse, MockFetchBackend};
+        use std::time::Duration;
+
+        let mock = MockFetchBackend::new()
+            .route(
+                "POST",
+                "https://api.example.com/users",
+                FetchResponse::json(201, &json!({ "id": 1 })).with_header("X-Request-Id", "r1"),
+            )
+            .route(
+                "*",
+                "https://api.example.com/health",
+                FetchResponse::new(204, ""),
+            );
+        let engine = JsEngine::builder()
+            .fetch_backend(mock.clone())
+            .fetch_policy(FetchPolicy::new().timeout(Duration::from_secs(1)))
+            .build()
+            .expect("valid");
+        let code = r#"
+            const created = await fetch('https://api.example.com/users', {
+                method: 'POST',
+                headers: { 'Authorization': 'Bearer t' },
+                params: { dry: true },
+                body: { name: 'alice' },
+            });
+            const health = await fetch(new Request('https://api.example.com/health', { method: 'HEAD' }));
+            const missing = await fetch('https://api.example.com/missing');
+            const posted = await fetch(new Request('https://api.example.com/users', { method: 'POST', body: 'hello' }));
+            return {
+                created: [created.status, created.statusText, created.headers.get('x-request-id'), await created.json()],
+                health: [health.status, health.ok],
+                missing: [missing.status, missing.url],
+                posted: posted.status,
+            };
+        "#;
+        let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+        assert_eq!(
+            ret.0,
+            json!({
+                "created": [201, "Created", "r1", { "id": 1 }],
+                "health": [204, true],
+                "missing": [404, "https://api.example.com/missing"],
+                "posted": 201,
+            })
+        );
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 4);
+        let req = &requests[0];
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.url, "https://api.example.com/users?dry=true");
+        assert_eq!(req.header("authorization"), Some("Bearer t"));
+        assert_eq!(req.header("Content-Type"), Some("application/json"));
+        assert_eq!(req.body.as_deref(), Some(&br#"{"name":"alice"}"#[..]));
+        assert_eq!(req.timeout, Some(Duration::from_secs(1)));
+        assert_eq!(requests[1].method, "HEAD");
+        assert_eq!(requests[3].body.as_deref(), Some(&b"hello"[..]));
+    }
+
+    #[cfg(feature = "fetch")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn fetch_response_and_headers_should_follow_the_spec() {
+        let engine = JsEngine::builder().build().expect("valid");
+        let code = r#"
+            const headers = new Headers([['X-A', '1'], ['x-a', '2']]);
+            headers.set('Content-Type', 'text/plain');
+            const res = new Response('{"a":1}', { status: 201, headers: { 'X-B': 'b' } });
+            const copy = res.clone();
+            const buf = await copy.arrayBuffer();
+            return {
+                a: headers.get('X-A'),
+                keys: [...headers.keys()],
+                missing: headers.get('x-c'),
+                ok: res.ok,
+                status: res.status,
+                b: res.headers.get('x-b'),
+                json: await res.json(),
+                used: res.bodyUsed,
+                bytes: buf.byteLength,
+                notFound: new Response(null, { status: 404 }).ok,
+            };
+        "#;
+        let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+        assert_eq!(
+            ret.0,
+            json!({
+                "a": "1, 2",
+                "keys": ["content-type", "x-a"],
+                "missing": null,
+                "ok": true,
+                "status": 201,
+                "b": "b",
+                "json": {"a": 1},
+                "used": true,
+                "bytes": 7,
+                "notFound": false,
+            })
+        );
+    }
+
+    #[cfg(feature = "fetch")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn fetch_should_send_requests_and_read_responses() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // a minimal http server which echoes the request line and headers back
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("valid");
+        let addr = listener.local_addr().expect("valid");
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("valid");
+            let mut buf = vec![0u8; 4096];
+            let n = stream.read(&mut buf).await.expect("valid");
+            let req = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+            let body = serde_json::to_string(&json!({
+                "line": req.lines().next(),
+                "token": req.contains("x-token: secret"),
+                "body": req.ends_with("{\"a\":1}"),
+            }))
+            .expect("valid");
+            let res = format!(
+                "HTTP/1.1 418 I'm a teapot\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(res.as_bytes()).await.expect("valid");
+        });
+
+        let engine = JsEngine::builder().build().expect("valid");
+        let code = r#"
+            const res = await fetch(req.url, {
+                method: 'post',
+                headers: { 'X-Token': 'secret' },
+                params: { q: 'a b' },
+                body: { a: 1 },
+                timeout: 5000,
+            });
+            return {
+                status: res.status,
+                ok: res.ok,
+                type: res.headers.get('content-type'),
+                data: await res.json(),
+            };
+        "#;
+        let req = JsonValue(json!({ "url": format!("http://{}/echo", addr) }));
+        let ret = engine.run(code, req).await.expect("valid");
+        assert_eq!(
+            ret.0,
+            json!({
+                "status": 418,
+                "ok": false,
+                "type": "application/json",
+                "data": {
+                    "line": "post /echo?q=a+b http/1.1",
+                    "token": true,
+                    "body": true,
+                },
+            })
+        );
+    }
+
+    #[cfg(feature = "fetch")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn fetch_client_should_be_shared_by_the_pool() {
+        use crate::{FetchClientConfig, JsEnginePool};
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // a keep-alive http server which echoes the user agent and counts the connections
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("valid");
+        let addr = listener.local_addr().expect("valid");
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 4096];
+                    while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                        let req = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                        let body = req
+                            .lines()
+                            .filter(|line| {
+                                line.starts_with("user-agent") || line.starts_with("x-tenant")
+                            })
+                            .collect::<Vec<_>>()
+                            .join(";");
+                        let res = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        if stream.write_all(res.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        let config = FetchClientConfig::new()
+            .user_agent("easy-qjs")
+            .default_header("X-Tenant", "t1");
+        let pool = JsEnginePool::builder(JsEngine::builder().fetch_client(config))
+            .size(2)
+            .build()
+            .expect("valid");
+        let (a, b) = (
+            pool.get().await.expect("valid"),
+            pool.get().await.expect("valid"),
+        );
+        let code = "return await (await fetch(req.url)).text();";
+        let req = JsonValue(json!({ "url": format!("http://{}/", addr) }));
+        for engine in [&a, &b, &a] {
+            let ret = engine.run(code, req.clone()).await.expect("valid");
+            assert_eq!(ret.0, json!("user-agent: easy-qjs;x-tenant: t1"));
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "fetch")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn fetch_policy_should_be_enforced() {
+        use crate::FetchPolicy;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // answers every request with a 16 bytes body
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("valid");
+        let addr = listener.local_addr().expect("valid");
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                let res = "HTTP/1.1 200 OK\r\ncontent-length: 16\r\nconnection: close\r\n\r\n0123456789abcdef";
+                let _ = stream.write_all(res.as_bytes()).await;
+            }
+        });
+
+        let code = r#"
+            const ret = [];
+            for (const url of req.urls) {
+                try {
+                    ret.push((await (await fetch(url)).text()).length);
+                } catch (e) {
+                    ret.push(e.message.includes(req.reason));
+                }
+            }
+            return ret;
+        "#;
+        let run = |policy: FetchPolicy, urls: Vec<String>, reason: &str| {
+            let engine = JsEngine::builder()
+                .fetch_policy(policy)
+                .build()
+                .expect("valid");
+            let req = JsonValue(json!({ "urls": urls, "reason": reason }));
+            async move { engine.run(code, req).await.expect("valid").0 }
+        };
+        let local = format!("http://127.0.0.1:{}/", addr.port());
+        let localhost = format!("http://localhost:{}/", addr.port());
+
+        let policy = FetchPolicy::new().block_private_ips(true);
+        let urls = vec![local.clone()];
+        assert_eq!(run(policy, urls, "private address").await, json!([true]));
+        // the resolver drops the loopback address of localhost
+        let policy = FetchPolicy::new().block_private_ips(true);
+        let urls = vec![localhost.clone()];
+        assert_eq!(run(policy, urls, "public address").await, json!([true]));
+
+        let policy = FetchPolicy::new().allow_host("example.com");
+        let urls = vec![local.clone()];
+        assert_eq!(run(policy, urls, "not allowed").await, json!([true]));
+
+        let policy = FetchPolicy::new().allowed_schemes(["https"]);
+        let urls = vec![local.clone()];
+        assert_eq!(run(policy, urls, "not allowed").await, json!([true]));
+
+        let policy = FetchPolicy::new().max_response_size(8);
+        let urls = vec![local.clone()];
+        assert_eq!(
+            run(policy, urls, "larger than 8 bytes").await,
+            json!([true])
+        );
+
+        let policy = FetchPolicy::new().max_requests(2);
+        let urls = vec![local.clone(), local.clone(), local.clone()];
+        assert_eq!(
+            run(policy, urls, "more than 2 requests").await,
+            json!([16, 16, true])
+        );
+    }
+
+    #[cfg(feature = "fetch")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn fetch_errors_should_be_rejected() {
+        let engine = JsEngine::builder().build().expect("valid");
+        let code = r#"
+            const errors = [];
+            for (const input of [undefined, 'not a url', { url: 'http://127.0.0.1:1/' }]) {
+                try {
+                    await fetch(input);
+                } catch (e) {
+                    errors.push(e instanceof Error);
+                }
+            }
+            return errors;
+        "#;
+        let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+        assert_eq!(ret.0, json!([true, true, true]));
     }
 }
@@ -1,3 +1,4 @@
+use crate::JsException;
 use snafu::Snafu;
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
@@ -13,6 +14,24 @@ pub enum Error {
     JsContext { source: js::Error },
     #[snafu(display("Failed to execute javascript code"))]
     JsExecute { source: js::Error },
-    #[snafu(display("Javascript code returned an error: {}", msg))]
-    JsResult { msg: String },
+    // javascript code threw an exception
+    #[snafu(display("Javascript code threw an exception: {}", exception))]
+    JsException { exception: JsException },
+    // execution is cancelled by the caller
+    #[snafu(display("Javascript execution is cancelled"))]
+    Cancelled,
+    // execution exceeds the deadline
+    #[snafu(display("Javascript execution timed out"))]
+    Timeout,
+    // failed to create the http client of fetch
+    #[cfg(feature = "fetch")]
+    #[snafu(display("Failed to create the fetch client: {}", source))]
+    FetchClient { source: crate::FetchError },
+    // the processors are exposed, except the namespaces which would shadow existing globals
+    #[cfg(feature = "builtin_processor")]
+    #[snafu(display(
+        "The processor namespaces shadow existing globals: {}",
+        namespaces.join(", ")
+    ))]
+    NamespaceConflict { namespaces: Vec<String> },
 }
@@ -0,0 +1,213 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use crate::{error::*, sync::lock, JsException, JsonOptions, JsonValue};
+use js::{Ctx, FromJs, Func, Function, Object, This};
+use tokio::sync::oneshot;
+
+type Settled<T> = Result<T, JsException>;
+
+/// The result of a javascript promise, which could be awaited outside of the context.
+#[derive(Debug)]
+pub(crate) struct PendingResult<T = JsonValue>(oneshot::Receiver<Settled<T>>);
+
+impl PendingResult {
+    /// Subscribe to the settlement of the given promise (or plain value), which is converted
+    /// into json according to the options.
+    pub(crate) fn new<'js>(
+        ctx: Ctx<'js>,
+        value: js::Value<'js>,
+        options: JsonOptions,
+    ) -> Result<Self, js::Error> {
+        Self::with(ctx, value, move |ctx, value| {
+            JsonValue::from_js_with(ctx, value, &options)
+        })
+    }
+}
+
+impl<T: Send + 'static> PendingResult<T> {
+    /// Subscribe to the settlement of the given promise (or plain value), which is converted
+    /// by the given function.
+    pub(crate) fn with<'js, F>(
+        ctx: Ctx<'js>,
+        value: js::Value<'js>,
+        convert: F,
+    ) -> Result<Self, js::Error>
+    where
+        F: Fn(Ctx<'js>, js::Value<'js>) -> Result<T, js::Error> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let tx = Arc::new(Mutex::new(Some(tx)));
+        let resolve = |tx: &Arc<Mutex<Option<oneshot::Sender<Settled<T>>>>>, ret| {
+            let tx = lock(tx).take();
+            if let Some(tx) = tx {
+                // receiver might be dropped if the caller is no longer interested
+                let _ = tx.send(ret);
+            }
+        };
+
+        let then = match value.as_object() {
+            Some(obj) => obj.get::<_, Option<Function>>("then")?,
+            None => None,
+        };
+        let then = match then {
+            Some(then) => then,
+            None => {
+                resolve(&tx, convert(ctx, value).map_err(Into::into));
+                return Ok(Self(rx));
+            }
+        };
+
+        let on_ok = Func::new("onSuccess", {
+            let tx = tx.clone();
+            move |ctx: Ctx<'js>, value: js::Value<'js>| {
+                resolve(&tx, convert(ctx, value).map_err(Into::into));
+            }
+        });
+        let on_err = Func::new("onError", {
+            move |ctx: Ctx<'js>, value: js::Value<'js>| {
+                let exception = JsException::from_js(ctx, value).unwrap_or_else(Into::into);
+                resolve(&tx, Err(exception));
+            }
+        });
+        let obj = Object::from_value(value)?;
+        then.call::<_, ()>((This(obj), on_ok, on_err))?;
+        Ok(Self(rx))
+    }
+
+    pub(crate) async fn wait(self) -> Result<T> {
+        match self.0.await {
+            Ok(ret) => ret.map_err(|exception| Error::JsException { exception }),
+            Err(_) => Err(Error::JsException {
+                exception: JsException::new("InternalError", "promise is dropped before settled"),
+            }),
+        }
+    }
+}
+
+impl JsException {
+    pub fn new(name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            message: message.into(),
+            stack: None,
+            file: None,
+            line: None,
+            column: None,
+        }
+    }
+
+    /// fill the file and line / column from the first frame of the stack, if not provided
+    fn with_location_from_stack(mut self) -> Self {
+        let location = self.stack.as_deref().and_then(|stack| {
+            stack.lines().find_map(|line| {
+                let (_, location) = line.trim().rsplit_once('(')?;
+                parse_location(location.strip_suffix(')')?)
+            })
+        });
+        if let Some((file, line, column)) = location {
+            if self.file.is_none() {
+                self.file = Some(file);
+            }
+            if self.line.is_none() {
+                self.line = Some(line);
+                self.column = column;
+            }
+        }
+        self
+    }
+}
+
+/// whether the stack is of an error thrown by the parser, whose first frame is the location
+/// in the source, e.g. `at script:1`, rather than a function, e.g. `at default (script:1)`
+fn is_syntax_error(stack: &str) -> bool {
+    match stack.lines().next().map(str::trim) {
+        Some(frame) => frame.starts_with("at ") && !frame.ends_with(')'),
+        None => false,
+    }
+}
+
+/// parse `file:line` or `file:line:column`
+fn parse_location(s: &str) -> Option<(String, u32, Option<u32>)> {
+    let (rest, last) = s.rsplit_once(':')?;
+    let last = last.parse().ok()?;
+    match rest.rsplit_once(':') {
+        Some((file, line)) => match line.parse() {
+            Ok(line) => Some((file.to_owned(), line, Some(last))),
+            Err(_) => Some((rest.to_owned(), last, None)),
+        },
+        None => Some((rest.to_owned(), last, None)),
+    }
+}
+
+impl<'js> FromJs<'js> for JsException {
+    fn from_js(ctx: Ctx<'js>, val: js::Value<'js>) -> Result<Self, js::Error> {
+        let exception = match val.as_object() {
+            Some(obj) if obj.is_error() => Self {
+                name: obj
+                    .get::<_, Option<String>>("name")?
+                    .unwrap_or_else(|| "Error".to_owned()),
+                message: obj.get::<_, Option<String>>("message")?.unwrap_or_default(),
+                stack: obj.get::<_, Option<String>>("stack")?,
+                file: obj.get::<_, Option<String>>("fileName")?,
+                line: obj.get::<_, Option<u32>>("lineNumber")?,
+                column: obj.get::<_, Option<u32>>("columnNumber")?,
+            },
+            // a non-error value is thrown, e.g. `throw "oops"`
+            _ => Self::new("Error", JsonValue::from_js(ctx, val)?.to_string()),
+        };
+        Ok(exception.with_location_from_stack())
+    }
+}
+
+impl From<js::Error> for JsException {
+    /// rquickjs keeps the message, location and stack of the synchronous exceptions but not
+    /// their names, so only the syntax errors could be told apart from their stacks
+    fn from(e: js::Error) -> Self {
+        match e {
+            js::Error::Exception {
+                message,
+                file,
+                line,
+                stack,
+            } => Self {
+                name: match is_syntax_error(&stack) {
+                    true => "SyntaxError",
+                    false => "Error",
+                }
+                .to_owned(),
+                message,
+                stack: (!stack.is_empty()).then_some(stack),
+                file: (!file.is_empty()).then_some(file),
+                line: u32::try_from(line).ok(),
+                column: None,
+            }
+            .with_location_from_stack(),
+            e => Self::new("InternalError", e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for JsException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)?;
+        match (&self.file, self.line, self.column) {
+            (Some(file), Some(line), Some(column)) => write!(f, " ({}:{}:{})", file, line, column),
+            (Some(file), Some(line), None) => write!(f, " ({}:{})", file, line),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Report javascript exceptions with details, and other errors as execution failures.
+pub(crate) fn js_error(e: js::Error) -> Error {
+    if e.is_exception() {
+        Error::JsException {
+            exception: e.into(),
+        }
+    } else {
+        Error::JsExecute { source: e }
+    }
+}
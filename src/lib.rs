@@ -40,39 +40,206 @@
 #![cfg_attr(docsrs, feature(doc_auto_cfg, doc_cfg))]
 #![cfg_attr(test, allow(clippy::float_cmp))]
 
+mod builder;
 mod builtins;
 mod cancellation;
 mod engine;
 pub(crate) mod error;
+mod exception;
+mod module;
+mod pool;
+mod processor;
+mod script;
+mod serde_js;
+mod sync;
 
 mod value;
 
 use std::{
     sync::{atomic::AtomicBool, Arc},
-    time::Instant,
+    time::{Instant, SystemTime},
 };
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 pub use error::Error;
+pub use module::{DirectoryResolver, EmbeddedResolver, MemoryResolver, ModuleResolver};
+pub use pool::{JsEnginePool, JsEnginePoolBuilder, PoolMetrics, PooledEngine};
+#[cfg(feature = "dispatcher")]
+pub use processor::ProcessorRegistry;
+pub use serde_js::{from_js, to_js};
 // re-exports
 pub use js;
 
 #[async_trait]
 pub trait Processor: Send + Sync + 'static {
-    async fn call(&self, args: JsonValue) -> Result<JsonValue, String>;
+    async fn call(&self, args: JsonValue) -> Result<JsonValue, ProcessorError>;
+}
+
+/// Error returned by a [`Processor`]. It is thrown in javascript as a `DispatchError`, or as an
+/// `Error` with the `code` and `details` by the functions registered with
+/// [`JsEngine::register_fn`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessorError {
+    /// machine-readable error code, e.g. `not_found`
+    pub code: Option<String>,
+    /// human-readable error message
+    pub message: String,
+    /// extra details of the error
+    pub details: Option<JsonValue>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct JsonValue(serde_json::Value);
 
+/// How [`JsonValue`] is converted from and into javascript values, see
+/// [`JsonValue::from_js_with`] and [`JsonValue::into_js_with`]. The default keeps the
+/// conversion of the plain `FromJs` / `IntoJs` impls, [`JsonOptions::lossless`] converts
+/// everything it could without losing data. Circular, too deep or too large values fail the
+/// conversion, so that a script could not exhaust the stack or memory of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonOptions {
+    big_int: bool,
+    dates: bool,
+    collections: bool,
+    binary: BinaryEncoding,
+    strict: bool,
+    circular_marker: bool,
+    max_depth: usize,
+    max_size: usize,
+}
+
+/// How typed arrays, `DataView` and `ArrayBuffer` are converted into json.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BinaryEncoding {
+    /// like plain objects, i.e. `ArrayBuffer` becomes `{}` and typed arrays become
+    /// `{"0": 1, ...}`
+    #[default]
+    Object,
+    /// a base64 string of the bytes
+    Base64,
+    /// an array of the elements, or of the bytes for `DataView` and `ArrayBuffer`
+    Array,
+}
+
 pub struct JsEngine {
     #[allow(dead_code)]
     runtime: js::Runtime,
     pub context: js::Context,
+    /// how the requests and results of the runs are converted
+    json: JsonOptions,
+    #[cfg(feature = "dispatcher")]
+    sender: Option<flume::Sender<MsgChannel>>,
+    #[cfg(feature = "builtin_processor")]
+    processors: Option<ProcessorRegistry>,
+    /// the `expose` function of `dispatcher.js`, which only the host could call
+    #[cfg(feature = "builtin_processor")]
+    expose: std::sync::Mutex<Option<js::Persistent<js::Function<'static>>>>,
+    #[cfg(feature = "fetch")]
+    fetcher: Option<Arc<builtins::Fetcher>>,
+    #[cfg(feature = "console")]
+    console: Option<Arc<builtins::Console>>,
+    #[cfg(feature = "timers")]
+    timers: Option<Arc<builtins::Timers>>,
+    /// restores the globals after the runs, or keeps the builtins frozen
+    isolation: Option<builtins::isolation::Isolation>,
+    /// number of runs in progress, see `JsEngine::begin_run`
+    runs: std::sync::atomic::AtomicUsize,
+    /// number of runs started since the engine is built, read by the pool
+    started: std::sync::atomic::AtomicUsize,
+}
+
+/// A script compiled by [`JsEngine::compile`], which could be run repeatedly with
+/// [`JsEngine::run_compiled`] without parsing the code again.
+pub struct CompiledScript {
+    name: String,
+    bytecode: Vec<u8>,
+    handler: Option<js::Persistent<js::Function<'static>>>,
+    context: js::Context,
+}
+
+/// Builder to configure the runtime limits and builtins of a [`JsEngine`].
+#[derive(Debug, Clone)]
+pub struct JsEngineBuilder {
+    memory_limit: usize,
+    max_stack_size: usize,
+    gc_threshold: Option<usize>,
+    json: JsonOptions,
+    module_resolver: Option<module::SharedResolver>,
+    isolate_globals: bool,
+    /// whether the globals are restored when the engine is returned to a pool
+    reset_globals: bool,
+    freeze_builtins: bool,
+    #[cfg(feature = "console")]
+    console: bool,
+    #[cfg(feature = "console")]
+    console_sink: Option<builtins::SharedSink>,
+    #[cfg(feature = "fetch")]
+    fetch: bool,
+    #[cfg(feature = "timers")]
+    timers: bool,
+    #[cfg(feature = "fetch")]
+    fetch_policy: FetchPolicy,
+    #[cfg(feature = "fetch")]
+    fetch_backend: Option<builtins::SharedBackend>,
+    #[cfg(feature = "fetch")]
+    fetch_client: FetchClientConfig,
+    /// the reqwest backend shared by the engines built from the clones of this builder
+    #[cfg(feature = "fetch")]
+    reqwest_backend: builtins::LazyBackend,
     #[cfg(feature = "dispatcher")]
-    sender: flume::Sender<MsgChannel>,
+    sender: Option<flume::Sender<MsgChannel>>,
+    #[cfg(feature = "builtin_processor")]
+    processors: Option<ProcessorRegistry>,
+}
+
+/// Details of an exception thrown by the javascript code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsException {
+    /// the name of the error, e.g. `TypeError`
+    pub name: String,
+    /// the error message
+    pub message: String,
+    /// the javascript stack trace
+    pub stack: Option<String>,
+    /// the file (module name) where the exception is thrown
+    pub file: Option<String>,
+    /// the line number where the exception is thrown
+    pub line: Option<u32>,
+    /// the column number where the exception is thrown
+    pub column: Option<u32>,
+}
+
+/// The result of [`JsEngine::run_captured`], along with the console output of the run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunOutput {
+    /// the value returned by the script
+    pub value: JsonValue,
+    /// what the script wrote to `console`, in order
+    pub logs: Vec<LogEntry>,
+}
+
+/// A line written to `console` by a script.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub level: LogLevel,
+    pub timestamp: SystemTime,
+    /// the arguments joined by spaces, after the `%s` like substitutions in the first one
+    pub message: String,
+    pub args: Vec<JsonValue>,
+}
+
+/// Level of a [`LogEntry`], named after the `console` method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Log,
+    Warn,
+    Error,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -81,16 +248,24 @@ pub struct Cancellation {
     cancellation: Arc<AtomicBool>,
 }
 
+#[cfg(feature = "console")]
+pub use builtins::console::{ConsoleSink, MemorySink, StdoutSink, TracingSink};
 #[cfg(feature = "dispatcher")]
 pub use builtins::dispatcher::MsgChannel;
+#[cfg(feature = "fetch")]
+pub use builtins::fetch::{
+    FetchBackend, FetchClientConfig, FetchError, FetchPolicy, FetchRequest, FetchResponse,
+    MockFetchBackend,
+};
 
 #[cfg(feature = "dispatcher")]
 #[async_trait]
-impl<F> Processor for F
+impl<F, E> Processor for F
 where
-    F: Fn(JsonValue) -> Result<JsonValue, String> + Send + Sync + 'static,
+    F: Fn(JsonValue) -> Result<JsonValue, E> + Send + Sync + 'static,
+    E: Into<ProcessorError>,
 {
-    async fn call(&self, args: JsonValue) -> Result<JsonValue, String> {
-        (self)(args)
+    async fn call(&self, args: JsonValue) -> Result<JsonValue, ProcessorError> {
+        (self)(args).map_err(Into::into)
     }
 }
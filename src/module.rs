@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use js::{Ctx, Loaded, Loader, Module, Resolver};
+
+use crate::sync::{read, write};
+
+/// Provides the source of the modules the scripts `import`, e.g. `lib/crypto` for
+/// `import { sign } from "lib/crypto"`. Relative names like `./utils` are resolved against
+/// the name of the importing module before they are passed to the resolver.
+///
+/// A module is loaded once per engine, so later changes of its source only apply to new
+/// engines.
+pub trait ModuleResolver: Send + Sync + 'static {
+    /// Return the source of the module, or `None` if there is no module of the name.
+    fn resolve(&self, name: &str) -> io::Result<Option<String>>;
+}
+
+/// Keeps the modules in memory, e.g. to load them from a database. Clones share the modules.
+#[derive(Clone, Default)]
+pub struct MemoryResolver {
+    modules: Arc<RwLock<HashMap<String, String>>>,
+}
+
+/// Reads the modules from the files in a directory, e.g. `lib/crypto` from
+/// `<dir>/lib/crypto.js`. The names could not refer to the files outside of the directory.
+#[derive(Debug, Clone)]
+pub struct DirectoryResolver {
+    dir: PathBuf,
+    extensions: Vec<String>,
+}
+
+/// Serves the modules embedded in the binary, e.g. with `include_str!`:
+///
+/// ```
+/// use easy_qjs::EmbeddedResolver;
+///
+/// static MODULES: &[(&str, &str)] = &[("lib/math", "export const add = (a, b) => a + b;")];
+/// // or ("lib/crypto", include_str!("../js/lib/crypto.js"))
+/// let resolver = EmbeddedResolver::new(MODULES);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddedResolver {
+    modules: &'static [(&'static str, &'static str)],
+}
+
+impl MemoryResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the module, e.g. `MemoryResolver::new().with_module("lib/math", source)`.
+    pub fn with_module(self, name: impl Into<String>, source: impl Into<String>) -> Self {
+        self.insert(name, source);
+        self
+    }
+
+    /// Add or replace the module.
+    pub fn insert(&self, name: impl Into<String>, source: impl Into<String>) {
+        write(&self.modules).insert(name.into(), source.into());
+    }
+
+    /// Remove the module, returning its source.
+    pub fn remove(&self, name: &str) -> Option<String> {
+        write(&self.modules).remove(name)
+    }
+}
+
+impl ModuleResolver for MemoryResolver {
+    fn resolve(&self, name: &str) -> io::Result<Option<String>> {
+        let modules = read(&self.modules);
+        Ok(modules.get(name).cloned())
+    }
+}
+
+impl fmt::Debug for MemoryResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let modules = read(&self.modules);
+        f.debug_struct("MemoryResolver")
+            .field("modules", &modules.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl DirectoryResolver {
+    /// Read the modules from the directory, trying the name as is and then with the `js` and
+    /// `mjs` extensions.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            extensions: vec!["js".to_owned(), "mjs".to_owned()],
+        }
+    }
+
+    /// The extensions tried in order when the file of the name itself does not exist.
+    pub fn extensions<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extensions = extensions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// the path of the name in the directory, if it stays in the directory
+    fn path(&self, name: &str) -> Option<PathBuf> {
+        let mut path = self.dir.clone();
+        for component in Path::new(name).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        Some(path)
+    }
+}
+
+impl ModuleResolver for DirectoryResolver {
+    fn resolve(&self, name: &str) -> io::Result<Option<String>> {
+        let path = match self.path(name) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let with_extensions = self.extensions.iter().map(|ext| {
+            let mut file = path.clone().into_os_string();
+            file.push(".");
+            file.push(ext);
+            PathBuf::from(file)
+        });
+        for file in std::iter::once(path.clone()).chain(with_extensions) {
+            match std::fs::read_to_string(&file) {
+                Ok(source) => return Ok(Some(source)),
+                // a directory of the same name, e.g. `lib/crypto/` next to `lib/crypto.js`
+                Err(_) if file.is_dir() => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl EmbeddedResolver {
+    /// Serve the modules given as `(name, source)` pairs.
+    pub const fn new(modules: &'static [(&'static str, &'static str)]) -> Self {
+        Self { modules }
+    }
+}
+
+impl ModuleResolver for EmbeddedResolver {
+    fn resolve(&self, name: &str) -> io::Result<Option<String>> {
+        let source = self.modules.iter().find(|(n, _)| *n == name);
+        Ok(source.map(|(_, source)| (*source).to_owned()))
+    }
+}
+
+/// A [`ModuleResolver`] shared by the engines built from the same builder.
+#[derive(Clone)]
+pub(crate) struct SharedResolver(pub(crate) Arc<dyn ModuleResolver>);
+
+impl SharedResolver {
+    /// load the modules of the runtime with the resolver
+    pub(crate) fn install(&self, rt: &js::Runtime) {
+        rt.set_loader(self.clone(), self.clone());
+    }
+}
+
+impl Resolver for SharedResolver {
+    fn resolve<'js>(&mut self, _ctx: Ctx<'js>, base: &str, name: &str) -> js::Result<String> {
+        normalize(base, name).ok_or_else(|| {
+            let message = "the relative name refers to a module outside of the root";
+            js::Error::new_resolving_message(base, name, message)
+        })
+    }
+}
+
+impl Loader for SharedResolver {
+    fn load<'js>(&mut self, ctx: Ctx<'js>, name: &str) -> js::Result<Module<'js, Loaded>> {
+        match self.0.resolve(name) {
+            Ok(Some(source)) => Ok(Module::new(ctx, name, source)?.into_loaded()),
+            Ok(None) => Err(js::Error::new_loading(name)),
+            Err(e) => Err(js::Error::new_loading_message(name, e.to_string())),
+        }
+    }
+}
+
+impl fmt::Debug for SharedResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedResolver").finish()
+    }
+}
+
+/// Resolve the relative name, e.g. `./utils`, against the directory of the importing module.
+/// Other names are used as is.
+fn normalize(base: &str, name: &str) -> Option<String> {
+    if !name.starts_with("./") && !name.starts_with("../") {
+        return Some(name.to_owned());
+    }
+    let mut parts: Vec<&str> = base.split('/').collect();
+    // the importing module itself
+    parts.pop();
+    for part in name.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_names_should_be_normalized() {
+        assert_eq!(
+            normalize("script", "lib/crypto").as_deref(),
+            Some("lib/crypto")
+        );
+        assert_eq!(normalize("script", "./utils").as_deref(), Some("utils"));
+        assert_eq!(
+            normalize("lib/crypto", "./hash").as_deref(),
+            Some("lib/hash")
+        );
+        assert_eq!(normalize("lib/a/b", "../c/./d").as_deref(), Some("lib/c/d"));
+        assert_eq!(normalize("script", "../secret"), None);
+    }
+
+    #[test]
+    fn directory_resolver_should_stay_in_the_directory() {
+        let resolver = DirectoryResolver::new("/srv/js");
+        assert_eq!(
+            resolver.path("lib/./crypto"),
+            Some(PathBuf::from("/srv/js/lib/crypto"))
+        );
+        assert_eq!(resolver.path("../etc/passwd"), None);
+        assert_eq!(resolver.path("/etc/passwd"), None);
+    }
+}
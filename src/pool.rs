@@ -0,0 +1,320 @@
+use std::{
+    fmt,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{error::*, sync::lock, JsEngine, JsEngineBuilder};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+
+const DEFAULT_POOL_SIZE: usize = 4;
+
+/// Builder to configure a [`JsEnginePool`].
+#[derive(Debug, Clone)]
+pub struct JsEnginePoolBuilder {
+    engine: JsEngineBuilder,
+    size: usize,
+    global_js: Vec<(String, String)>,
+    max_runs: Option<usize>,
+    max_memory: Option<usize>,
+}
+
+/// A pool of pre-warmed engines sharing the same configuration and global js. The globals of
+/// an engine are restored to that setup when it is returned, see
+/// [`JsEngineBuilder::isolate_globals`] for what is restored.
+#[derive(Debug, Clone)]
+pub struct JsEnginePool {
+    inner: Arc<PoolInner>,
+}
+
+/// An engine checked out from the pool. It is returned to the pool (or recycled) when dropped.
+pub struct PooledEngine {
+    entry: Option<PoolEntry>,
+    pool: Arc<PoolInner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Metrics of a [`JsEnginePool`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolMetrics {
+    /// max number of engines in the pool
+    pub size: usize,
+    /// number of engines ready to be checked out
+    pub idle: usize,
+    /// number of engines checked out
+    pub in_use: usize,
+    /// number of engines created since the pool is built
+    pub created: usize,
+    /// number of engines dropped because of the run or memory limits
+    pub recycled: usize,
+    /// number of checkouts since the pool is built
+    pub checkouts: usize,
+}
+
+struct PoolInner {
+    config: JsEnginePoolBuilder,
+    idle: Mutex<Vec<PoolEntry>>,
+    permits: Arc<Semaphore>,
+    created: AtomicUsize,
+    recycled: AtomicUsize,
+    checkouts: AtomicUsize,
+}
+
+struct PoolEntry {
+    engine: JsEngine,
+}
+
+impl JsEnginePoolBuilder {
+    pub fn new(engine: JsEngineBuilder) -> Self {
+        Self {
+            engine,
+            size: DEFAULT_POOL_SIZE,
+            global_js: Vec::new(),
+            max_runs: None,
+            max_memory: None,
+        }
+    }
+
+    /// Number of engines in the pool.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size.max(1);
+        self
+    }
+
+    /// Global js loaded into every engine, see [`JsEngine::load_global_js`].
+    pub fn global_js(mut self, name: impl Into<String>, code: impl Into<String>) -> Self {
+        self.global_js.push((name.into(), code.into()));
+        self
+    }
+
+    /// Recycle an engine once it has run the given number of scripts, counting every run of
+    /// the engine, e.g. [`JsEngine::run`] or [`JsEngine::call_function`], whichever checkout
+    /// it belongs to.
+    pub fn max_runs(mut self, runs: usize) -> Self {
+        self.max_runs = Some(runs);
+        self
+    }
+
+    /// Recycle an engine once its runtime uses more memory (in bytes) than the given value.
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Build the pool and pre-warm all the engines.
+    pub fn build(self) -> Result<JsEnginePool> {
+        let size = self.size;
+        let inner = Arc::new(PoolInner {
+            idle: Mutex::new(Vec::with_capacity(size)),
+            permits: Arc::new(Semaphore::new(size)),
+            config: self,
+            created: AtomicUsize::new(0),
+            recycled: AtomicUsize::new(0),
+            checkouts: AtomicUsize::new(0),
+        });
+        let entries = (0..size)
+            .map(|_| inner.create_entry())
+            .collect::<Result<Vec<_>>>()?;
+        lock(&inner.idle).extend(entries);
+        Ok(JsEnginePool { inner })
+    }
+}
+
+impl JsEnginePool {
+    pub fn builder(engine: JsEngineBuilder) -> JsEnginePoolBuilder {
+        JsEnginePoolBuilder::new(engine)
+    }
+
+    /// Check out an engine, waiting until one is available.
+    pub async fn get(&self) -> Result<PooledEngine> {
+        let permit = self
+            .inner
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("pool semaphore is never closed");
+        let entry = lock(&self.inner.idle).pop();
+        let entry = match entry {
+            Some(entry) => entry,
+            None => self.inner.create_entry()?,
+        };
+        self.inner.checkouts.fetch_add(1, Ordering::Relaxed);
+        Ok(PooledEngine {
+            entry: Some(entry),
+            pool: self.inner.clone(),
+            _permit: permit,
+        })
+    }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        let size = self.inner.config.size;
+        let in_use = size - self.inner.permits.available_permits();
+        PoolMetrics {
+            size,
+            idle: lock(&self.inner.idle).len(),
+            in_use,
+            created: self.inner.created.load(Ordering::Relaxed),
+            recycled: self.inner.recycled.load(Ordering::Relaxed),
+            checkouts: self.inner.checkouts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl PoolInner {
+    fn create_entry(&self) -> Result<PoolEntry> {
+        let engine = self.config.engine.clone().reset_globals(true).build()?;
+        for (name, code) in &self.config.global_js {
+            engine.load_global_js(name, code)?;
+        }
+        self.created.fetch_add(1, Ordering::Relaxed);
+        Ok(PoolEntry { engine })
+    }
+
+    fn should_recycle(&self, entry: &PoolEntry) -> bool {
+        if matches!(self.config.max_runs, Some(max) if entry.runs() >= max) {
+            return true;
+        }
+        match self.config.max_memory {
+            Some(max) => {
+                let used = entry.engine.runtime.memory_usage().memory_used_size;
+                usize::try_from(used).unwrap_or_default() > max
+            }
+            None => false,
+        }
+    }
+}
+
+impl PoolEntry {
+    /// number of runs of the engine since it is created
+    fn runs(&self) -> usize {
+        self.engine.started.load(Ordering::Relaxed)
+    }
+}
+
+impl Deref for PooledEngine {
+    type Target = JsEngine;
+
+    fn deref(&self) -> &Self::Target {
+        &self
+            .entry
+            .as_ref()
+            .expect("entry exists until dropped")
+            .engine
+    }
+}
+
+impl Drop for PooledEngine {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            if self.pool.should_recycle(&entry) {
+                debug!("recycle engine after {} runs", entry.runs());
+                self.pool.recycled.fetch_add(1, Ordering::Relaxed);
+            } else {
+                entry.engine.reset_globals();
+                lock(&self.pool.idle).push(entry);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for PooledEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledEngine").finish()
+    }
+}
+
+impl fmt::Debug for PoolInner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolInner")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JsonValue;
+    use serde_json::json;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn pool_should_recycle_engines() {
+        let pool = JsEnginePool::builder(JsEngine::builder())
+            .size(2)
+            .max_runs(2)
+            .global_js("lib", "export default { add: (a, b) => a + b }")
+            .build()
+            .expect("valid");
+
+        let (a, b) = (
+            pool.get().await.expect("valid"),
+            pool.get().await.expect("valid"),
+        );
+        let metrics = pool.metrics();
+        assert_eq!((metrics.idle, metrics.in_use), (0, 2));
+        let (ra, rb) = tokio::join!(
+            a.run("return add(1, 2);", JsonValue::null()),
+            b.run("return add(3, 4);", JsonValue::null())
+        );
+        assert_eq!(ra.expect("valid").0, json!(3));
+        assert_eq!(rb.expect("valid").0, json!(7));
+        drop((a, b));
+
+        for _ in 0..2 {
+            let engine = pool.get().await.expect("valid");
+            engine
+                .run("return add(1, 1);", JsonValue::null())
+                .await
+                .expect("valid");
+        }
+        let metrics = pool.metrics();
+        assert_eq!(metrics.checkouts, 4);
+        assert_eq!(metrics.recycled, 2);
+        assert_eq!((metrics.idle, metrics.in_use), (0, 0));
+
+        // recycled engines are re-created on demand
+        let engine = pool.get().await.expect("valid");
+        let ret = engine
+            .run("return add(2, 2);", JsonValue::null())
+            .await
+            .expect("valid");
+        assert_eq!(ret.0, json!(4));
+        assert_eq!(pool.metrics().created, 3);
+
+        // the runs are counted rather than the checkouts
+        engine
+            .run("return add(2, 2);", JsonValue::null())
+            .await
+            .expect("valid");
+        drop(engine);
+        assert_eq!(pool.metrics().recycled, 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn pool_should_restore_the_globals_of_returned_engines() {
+        let pool = JsEnginePool::builder(JsEngine::builder())
+            .size(1)
+            .global_js("lib", "export default { add: (a, b) => a + b }")
+            .build()
+            .expect("valid");
+
+        let engine = pool.get().await.expect("valid");
+        let code = "globalThis.leaked = 1; delete globalThis.add;";
+        engine.run(code, JsonValue::null()).await.expect("valid");
+        // the globals are kept between the runs of the same checkout
+        let code = "return [typeof leaked, typeof add];";
+        let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+        assert_eq!(ret.0, json!(["number", "undefined"]));
+        drop(engine);
+
+        let engine = pool.get().await.expect("valid");
+        let ret = engine.run(code, JsonValue::null()).await.expect("valid");
+        assert_eq!(ret.0, json!(["undefined", "function"]));
+        assert_eq!(pool.metrics().created, 1);
+    }
+}
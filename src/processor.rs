@@ -0,0 +1,191 @@
+use std::fmt;
+
+use crate::{JsonValue, ProcessorError};
+
+impl ProcessorError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            code: None,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    pub fn with_details(mut self, details: JsonValue) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+impl From<String> for ProcessorError {
+    fn from(message: String) -> Self {
+        Self::new(message)
+    }
+}
+
+impl From<&str> for ProcessorError {
+    fn from(message: &str) -> Self {
+        Self::new(message)
+    }
+}
+
+impl fmt::Display for ProcessorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "[{}] {}", code, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ProcessorError {}
+
+#[cfg(feature = "dispatcher")]
+pub use registry::ProcessorRegistry;
+
+#[cfg(feature = "dispatcher")]
+mod registry {
+    use std::{
+        collections::HashMap,
+        fmt,
+        future::Future,
+        marker::PhantomData,
+        sync::{Arc, RwLock},
+    };
+
+    use crate::{
+        sync::{read, write},
+        JsonValue, Processor, ProcessorError,
+    };
+    use async_trait::async_trait;
+    use serde::{de::DeserializeOwned, Serialize};
+
+    type ProcessorMap = HashMap<(String, String), Arc<dyn Processor>>;
+
+    /// A shared registry of processors, keyed by namespace and name. Processors could be
+    /// added or removed while the engine is running.
+    #[derive(Clone, Default)]
+    pub struct ProcessorRegistry {
+        processors: Arc<RwLock<ProcessorMap>>,
+    }
+
+    /// Processor wrapping an async function with typed args and return value.
+    struct TypedProcessor<F, Args, Ret, E> {
+        f: F,
+        _marker: PhantomData<fn(Args) -> (Ret, E)>,
+    }
+
+    impl ProcessorRegistry {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Register a processor. An existing processor with the same namespace and name is
+        /// replaced.
+        pub fn register(
+            &self,
+            ns: impl Into<String>,
+            name: impl Into<String>,
+            processor: impl Processor,
+        ) {
+            self.insert(ns.into(), name.into(), Arc::new(processor));
+        }
+
+        /// Register an async function. The args are decoded from the javascript value, and
+        /// the return value is encoded back, with serde.
+        pub fn register_fn<F, Fut, Args, Ret, E>(
+            &self,
+            ns: impl Into<String>,
+            name: impl Into<String>,
+            f: F,
+        ) where
+            F: Fn(Args) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = Result<Ret, E>> + Send + 'static,
+            Args: DeserializeOwned + 'static,
+            Ret: Serialize + 'static,
+            E: Into<ProcessorError> + 'static,
+        {
+            let processor = TypedProcessor {
+                f,
+                _marker: PhantomData,
+            };
+            self.insert(ns.into(), name.into(), Arc::new(processor));
+        }
+
+        /// Remove a processor, returns true if it was registered.
+        pub fn remove(&self, ns: &str, name: &str) -> bool {
+            write(&self.processors)
+                .remove(&(ns.to_owned(), name.to_owned()))
+                .is_some()
+        }
+
+        pub fn contains(&self, ns: &str, name: &str) -> bool {
+            read(&self.processors).contains_key(&(ns.to_owned(), name.to_owned()))
+        }
+
+        /// All the registered (namespace, name) pairs, sorted.
+        pub fn names(&self) -> Vec<(String, String)> {
+            let mut names: Vec<_> = read(&self.processors).keys().cloned().collect();
+            names.sort();
+            names
+        }
+
+        #[cfg(feature = "builtin_processor")]
+        pub(crate) fn get(&self, ns: &str, name: &str) -> Option<Arc<dyn Processor>> {
+            read(&self.processors)
+                .get(&(ns.to_owned(), name.to_owned()))
+                .cloned()
+        }
+
+        fn insert(&self, ns: String, name: String, processor: Arc<dyn Processor>) {
+            write(&self.processors).insert((ns, name), processor);
+        }
+    }
+
+    impl<'a> FromIterator<(&'a str, &'a str, Box<dyn Processor>)> for ProcessorRegistry {
+        fn from_iter<T: IntoIterator<Item = (&'a str, &'a str, Box<dyn Processor>)>>(
+            iter: T,
+        ) -> Self {
+            let registry = Self::new();
+            for (ns, name, processor) in iter {
+                registry.insert(ns.to_owned(), name.to_owned(), Arc::from(processor));
+            }
+            registry
+        }
+    }
+
+    impl fmt::Debug for ProcessorRegistry {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("ProcessorRegistry")
+                .field("processors", &self.names())
+                .finish()
+        }
+    }
+
+    #[async_trait]
+    impl<F, Fut, Args, Ret, E> Processor for TypedProcessor<F, Args, Ret, E>
+    where
+        F: Fn(Args) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Ret, E>> + Send + 'static,
+        Args: DeserializeOwned + 'static,
+        Ret: Serialize + 'static,
+        E: Into<ProcessorError> + 'static,
+    {
+        async fn call(&self, args: JsonValue) -> Result<JsonValue, ProcessorError> {
+            let args: Args = serde_json::from_value(args.into()).map_err(|e| {
+                ProcessorError::new(format!("invalid args: {}", e)).with_code("invalid_args")
+            })?;
+            let ret = (self.f)(args).await.map_err(Into::into)?;
+            let ret = serde_json::to_value(ret).map_err(|e| {
+                ProcessorError::new(format!("invalid return value: {}", e))
+                    .with_code("invalid_return")
+            })?;
+            Ok(ret.into())
+        }
+    }
+}
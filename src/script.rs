@@ -0,0 +1,279 @@
+use crate::{
+    error::*,
+    exception::{js_error, PendingResult},
+    CompiledScript, JsEngine, JsonValue,
+};
+use std::fmt;
+
+use js::{Ctx, Function, Module, Persistent};
+use tracing::debug;
+
+impl JsEngine {
+    /// Compile the code (the body of the async handler function, as in [`JsEngine::run`])
+    /// so that it could be run repeatedly.
+    pub fn compile(&self, name: &str, code: &str) -> Result<CompiledScript> {
+        let src = wrap_code(code);
+        debug!("code to compile: {}", src);
+        let ret: Result<_, js::Error> = self.context.with(|ctx| {
+            let m = Module::new(ctx, name, src)?;
+            let bytecode = m.write_object(false)?;
+            let handler = load_handler(ctx, m)?;
+            Ok((bytecode, handler))
+        });
+        let (bytecode, handler) = ret.map_err(js_error)?;
+        Ok(CompiledScript::new(name, bytecode, handler, self))
+    }
+
+    /// Load the script from the bytecode generated by [`CompiledScript::bytecode`].
+    ///
+    /// The bytecode must come from a trusted source, e.g. stored by the application itself and
+    /// loaded with the same version of this crate. quickjs does not validate the bytecode, so a
+    /// corrupted or crafted input could crash the process or corrupt its memory. Never load
+    /// bytecode given by the authors of the scripts, compile their code instead.
+    pub fn load_compiled(&self, name: &str, bytecode: &[u8]) -> Result<CompiledScript> {
+        let ret: Result<_, js::Error> = self.context.with(|ctx| {
+            let m = Module::read_object(ctx, bytecode)?;
+            load_handler(ctx, m)
+        });
+        let handler = ret.map_err(js_error)?;
+        Ok(CompiledScript::new(name, bytecode.to_vec(), handler, self))
+    }
+
+    /// Run the compiled script with the given request. The script must be compiled by the
+    /// same engine.
+    pub async fn run_compiled(&self, script: &CompiledScript, req: JsonValue) -> Result<JsonValue> {
+        let run = self.begin_run(script.name());
+        let ret: Result<PendingResult, js::Error> = self.context.with(|ctx| {
+            let fun = script.handler(ctx)?;
+            let req = req.into_js_with(ctx, &self.json)?;
+            PendingResult::new(ctx, fun.call((req, run.console(ctx)?))?, self.json)
+        });
+        ret.map_err(js_error)?.wait().await
+    }
+}
+
+impl CompiledScript {
+    fn new(
+        name: &str,
+        bytecode: Vec<u8>,
+        handler: Persistent<Function<'static>>,
+        engine: &JsEngine,
+    ) -> Self {
+        Self {
+            name: name.to_owned(),
+            bytecode,
+            handler: Some(handler),
+            context: engine.context.clone(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The quickjs bytecode of the script, which could be stored and loaded by
+    /// [`JsEngine::load_compiled`] later. It is specific to the version of quickjs, and should
+    /// be stored where the scripts could not tamper with it.
+    pub fn bytecode(&self) -> &[u8] {
+        &self.bytecode
+    }
+
+    fn handler<'js>(&self, ctx: Ctx<'js>) -> Result<Function<'js>, js::Error> {
+        match &self.handler {
+            Some(handler) => handler.clone().restore(ctx),
+            None => Err(js::Error::Unknown),
+        }
+    }
+}
+
+impl Drop for CompiledScript {
+    fn drop(&mut self) {
+        // the persistent handler must be released while holding the runtime lock
+        if let Some(handler) = self.handler.take() {
+            self.context.with(|ctx| drop(handler.restore(ctx)));
+        }
+    }
+}
+
+impl fmt::Debug for CompiledScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompiledScript")
+            .field("name", &self.name)
+            .field("bytecode_len", &self.bytecode.len())
+            .finish()
+    }
+}
+
+/// wrap the code as the body of the default exported async function, which receives the
+/// request and the `console` of the run. The body is put in a block, so that the code could
+/// still declare its own `console`. The `import` declarations at the beginning of the code are
+/// kept at the top level of the module, as is, so that the line numbers of the code do not
+/// change.
+pub(crate) fn wrap_code(code: &str) -> String {
+    let (imports, body) = split_imports(code);
+    format!(
+        r#"{}export default async function(req, console) {{ {{ {} }} }}"#,
+        imports, body
+    )
+}
+
+/// split the code after the leading static `import` declarations, including the comments
+/// between them
+fn split_imports(code: &str) -> (&str, &str) {
+    let mut end = 0;
+    loop {
+        let start = skip_blank(code, end);
+        let rest = &code[start..];
+        let is_import = rest.starts_with("import")
+            && rest[6..].starts_with(|c: char| c.is_whitespace() || "{*\"'".contains(c));
+        match is_import.then(|| import_end(code, start + 6)).flatten() {
+            Some(pos) => end = pos,
+            None => return code.split_at(end),
+        }
+    }
+}
+
+/// the end of the `import` declaration whose clause starts at `pos`, i.e. after the module
+/// name and the optional semicolon
+fn import_end(code: &str, mut pos: usize) -> Option<usize> {
+    let mut prev = "import";
+    while pos < code.len() {
+        pos = skip_blank(code, pos);
+        let rest = &code[pos..];
+        let quote = rest.chars().next()?;
+        if quote == '"' || quote == '\'' {
+            let len = string_len(rest, quote)?;
+            pos += len;
+            // a string could also be the name of an export, e.g. `{ "a-b" as ab }`
+            if prev == "from" || prev == "import" {
+                let end = skip_blank(code, pos);
+                return Some(match code[end..].starts_with(';') {
+                    true => end + 1,
+                    false => pos,
+                });
+            }
+            prev = "";
+        } else {
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(rest.len())
+                .max(quote.len_utf8());
+            prev = &rest[..len];
+            // not an import declaration, e.g. `import("dynamic")` or `import.meta`
+            if prev == "(" || prev == "." || prev == ";" {
+                return None;
+            }
+            pos += len;
+        }
+    }
+    None
+}
+
+/// skip the whitespaces and comments from `pos`
+fn skip_blank(code: &str, mut pos: usize) -> usize {
+    loop {
+        let rest = &code[pos..];
+        let trimmed = rest.trim_start();
+        pos += rest.len() - trimmed.len();
+        if trimmed.starts_with("//") {
+            pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        } else if let Some(comment) = trimmed.strip_prefix("/*") {
+            match comment.find("*/") {
+                Some(end) => pos += end + 4,
+                None => return code.len(),
+            }
+        } else {
+            return pos;
+        }
+    }
+}
+
+/// the length of the string literal at the start of `s`, including the quotes
+fn string_len(s: &str, quote: char) -> Option<usize> {
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            c if c == quote => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn load_handler<'js, S>(
+    ctx: Ctx<'js>,
+    m: Module<'js, js::Loaded<S>>,
+) -> Result<Persistent<Function<'static>>, js::Error> {
+    let fun = m.eval()?.get::<_, Function>("default")?;
+    Ok(Persistent::save(ctx, fun))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn compiled_script_should_be_reused() {
+        let engine = JsEngine::builder().build().expect("valid");
+        let script = engine
+            .compile("add", "return req.a + req.b;")
+            .expect("valid");
+        for i in 0..3 {
+            let ret = engine
+                .run_compiled(&script, JsonValue::object(json!({"a": i, "b": 1})))
+                .await
+                .expect("valid");
+            assert_eq!(ret.0, json!(i + 1));
+        }
+
+        let other = JsEngine::builder().build().expect("valid");
+        let loaded = other
+            .load_compiled(script.name(), script.bytecode())
+            .expect("valid");
+        let ret = other
+            .run_compiled(&loaded, JsonValue::object(json!({"a": 1, "b": 2})))
+            .await
+            .expect("valid");
+        assert_eq!(ret.0, json!(3));
+
+        // scripts are bound to the engine compiling them
+        assert!(engine
+            .run_compiled(&loaded, JsonValue::null())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn leading_imports_should_be_hoisted() {
+        let code = r#"
+            // helpers
+            import { sign } from "lib/crypto";
+            import * as math from './math'
+            import {
+                "a-b" as ab,
+                c,
+            } from "lib/names"; /* side effects */ import "lib/setup";
+            return sign(req);
+        "#;
+        let (imports, body) = split_imports(code);
+        assert!(imports.ends_with(r#"import "lib/setup";"#));
+        assert_eq!(body.trim(), "return sign(req);");
+
+        assert_eq!(split_imports("return 1;"), ("", "return 1;"));
+        let code = "import('lib/x').then(console.log);";
+        assert_eq!(split_imports(code), ("", code));
+        let code = "const a = 1; import { b } from 'c';";
+        assert_eq!(split_imports(code), ("", code));
+
+        // the line numbers of the code are kept
+        let wrapped = wrap_code("import a from 'a';\nthrow a;");
+        assert_eq!(
+            wrapped,
+            "import a from 'a';export default async function(req, console) { { \nthrow a; } }"
+        );
+    }
+}
@@ -0,0 +1,377 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use js::{Array, FromAtom, Object, Type};
+use serde::de::{
+    self,
+    value::{SeqDeserializer, StringDeserializer},
+    DeserializeSeed, IntoDeserializer, Visitor,
+};
+
+use super::Error;
+use crate::{
+    value::{bytes, unsupported, Builtin, Converter, CIRCULAR, MAX_SAFE_INTEGER},
+    BinaryEncoding,
+};
+
+/// Deserializes javascript values into rust values, like `JsonValue::from_js_with` then
+/// `serde_json::from_value`.
+pub(super) struct Deserializer<'a, 'js> {
+    converter: &'a mut Converter<'js>,
+    value: js::Value<'js>,
+}
+
+/// The elements of an array, a `Set` or a typed array.
+struct Seq<'a, 'js> {
+    converter: &'a mut Converter<'js>,
+    object: Object<'js>,
+    len: usize,
+    index: usize,
+}
+
+/// The properties of an object, or the entries of a `Map`.
+struct Map<'a, 'js> {
+    converter: &'a mut Converter<'js>,
+    entries: std::vec::IntoIter<(String, js::Value<'js>)>,
+    value: Option<js::Value<'js>>,
+}
+
+/// An enum in the externally tagged representation, i.e. `{ variant: value }`.
+struct Enum<'a, 'js> {
+    converter: &'a mut Converter<'js>,
+    variant: String,
+    value: js::Value<'js>,
+}
+
+impl<'a, 'js> Deserializer<'a, 'js> {
+    pub(super) fn new(converter: &'a mut Converter<'js>, value: js::Value<'js>) -> Self {
+        Self { converter, value }
+    }
+}
+
+fn visit<'de, 'js, V: Visitor<'de>>(
+    converter: &mut Converter<'js>,
+    value: js::Value<'js>,
+    visitor: V,
+) -> Result<V::Value, Error> {
+    let options = *converter.options();
+    match value.type_of() {
+        Type::Uninitialized | Type::Undefined | Type::Null => visitor.visit_unit(),
+        Type::Bool => visitor.visit_bool(value.as_bool().expect("checked bool")),
+        Type::Int => visitor.visit_i32(value.as_int().expect("checked int")),
+        Type::Float => {
+            let v = value.as_float().expect("checked float");
+            // quickjs stores integers beyond i32 as floats
+            if v.fract() == 0.0 && v.abs() <= MAX_SAFE_INTEGER as f64 {
+                match v >= 0.0 {
+                    true => visitor.visit_u64(v as u64),
+                    false => visitor.visit_i64(v as i64),
+                }
+            } else if v.is_finite() {
+                visitor.visit_f64(v)
+            } else if options.strict {
+                Err(unsupported("number", "json", "the number is not finite").into())
+            } else {
+                visitor.visit_unit()
+            }
+        }
+        Type::String => {
+            let v = value.into_string().expect("checked string").to_string()?;
+            visitor.visit_string(v)
+        }
+        Type::Array => {
+            let array = value.into_array().expect("checked array");
+            Seq::new(converter, array)?.visit(visitor)
+        }
+        Type::Function if options.strict => {
+            Err(unsupported("function", "json", "functions are not supported").into())
+        }
+        Type::Object => {
+            let object = value.into_object().expect("checked object");
+            visit_object(converter, object, visitor)
+        }
+        Type::Unknown if options.big_int => {
+            let s = converter.big_int_string(value)?;
+            if let Ok(v) = s.parse::<i64>() {
+                visitor.visit_i64(v)
+            } else if let Ok(v) = s.parse::<u64>() {
+                visitor.visit_u64(v)
+            } else if let Ok(v) = s.parse::<i128>() {
+                visitor.visit_i128(v)
+            } else {
+                visitor.visit_string(s)
+            }
+        }
+        ty if options.strict => {
+            Err(unsupported(ty.as_str(), "json", "the value is not supported").into())
+        }
+        _ => visitor.visit_unit(),
+    }
+}
+
+fn visit_object<'de, 'js, V: Visitor<'de>>(
+    converter: &mut Converter<'js>,
+    object: Object<'js>,
+    visitor: V,
+) -> Result<V::Value, Error> {
+    let options = *converter.options();
+    if let Some(builtin) = converter.builtin(&object)? {
+        match builtin {
+            Builtin::Date if options.dates => {
+                return match converter.date_string(object)? {
+                    Some(iso) => visitor.visit_string(iso),
+                    None => visitor.visit_unit(),
+                };
+            }
+            Builtin::Map if options.collections => {
+                converter.check_len(object.get("size")?)?;
+                let entries: Array = converter.classes()?.array_from.call((object,))?;
+                let mut x = Vec::with_capacity(converter.array_len(&entries)?);
+                for entry in entries.iter::<Array>() {
+                    let entry = entry?;
+                    x.push((converter.map_key(entry.get(0)?)?, entry.get(1)?));
+                }
+                return Map::new(converter, x).visit(visitor);
+            }
+            Builtin::Set if options.collections => {
+                converter.check_len(object.get("size")?)?;
+                let values: Array = converter.classes()?.array_from.call((object,))?;
+                return Seq::new(converter, values)?.visit(visitor);
+            }
+            Builtin::View | Builtin::ArrayBuffer => match options.binary {
+                BinaryEncoding::Object => {}
+                BinaryEncoding::Base64 => {
+                    return visitor.visit_string(STANDARD.encode(bytes(builtin, object)?));
+                }
+                BinaryEncoding::Array if object.contains_key("length")? => {
+                    let len = converter.check_len(object.get("length")?)?;
+                    let seq = Seq {
+                        converter,
+                        object,
+                        len,
+                        index: 0,
+                    };
+                    return seq.visit(visitor);
+                }
+                BinaryEncoding::Array => {
+                    let bytes = bytes(builtin, object)?;
+                    return visitor.visit_seq(SeqDeserializer::new(bytes.into_iter()));
+                }
+            },
+            _ => {}
+        }
+        if options.strict {
+            let message = "the object is not enabled in the options";
+            return Err(unsupported("object", "json", message).into());
+        }
+    }
+    let mut props = Vec::with_capacity(converter.check_len(object.len() as f64)?);
+    for prop in object.props() {
+        let (k, v) = prop?;
+        props.push((String::from_atom(k)?, v));
+    }
+    Map::new(converter, props).visit(visitor)
+}
+
+impl<'de, 'a, 'js> de::Deserializer<'de> for Deserializer<'a, 'js> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let Self { converter, value } = self;
+        if !converter.enter(&value)? {
+            return visitor.visit_str(CIRCULAR);
+        }
+        let ret = visit(converter, value.clone(), visitor);
+        converter.leave(&value);
+        ret
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value.type_of() {
+            Type::Uninitialized | Type::Undefined | Type::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let Self { converter, value } = self;
+        if value.is_string() {
+            let variant = value.into_string().expect("checked string").to_string()?;
+            let variant: StringDeserializer<Error> = variant.into_deserializer();
+            return visitor.visit_enum(variant);
+        }
+        let object = match value.as_object() {
+            Some(object) if object.len() == 1 => object.clone(),
+            _ => {
+                return Err(de::Error::custom(
+                    "expected a string or an object with one key",
+                ))
+            }
+        };
+        // the variant value could contain the enum itself, e.g. `a.A = a`
+        if !converter.enter(&value)? {
+            return Err(unsupported("object", "json", "circular reference").into());
+        }
+        let ret = visit_enum(converter, object, visitor);
+        converter.leave(&value);
+        ret
+    }
+
+    /// skip the ignored values instead of walking through them
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+/// visit the `{ variant: value }` object of an enum
+fn visit_enum<'de, 'js, V: Visitor<'de>>(
+    converter: &mut Converter<'js>,
+    object: Object<'js>,
+    visitor: V,
+) -> Result<V::Value, Error> {
+    let (variant, value) = match object.props().next() {
+        Some(prop) => prop?,
+        None => return Err(de::Error::custom("expected an object with one key")),
+    };
+    visitor.visit_enum(Enum {
+        converter,
+        variant: String::from_atom(variant)?,
+        value,
+    })
+}
+
+impl<'a, 'js> Seq<'a, 'js> {
+    fn new(converter: &'a mut Converter<'js>, array: Array<'js>) -> Result<Self, Error> {
+        Ok(Self {
+            len: converter.array_len(&array)?,
+            converter,
+            object: array.into_object(),
+            index: 0,
+        })
+    }
+
+    fn visit<'de, V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Error> {
+        let value = visitor.visit_seq(&mut self)?;
+        match self.index < self.len {
+            true => Err(de::Error::invalid_length(
+                self.len,
+                &"fewer elements in array",
+            )),
+            false => Ok(value),
+        }
+    }
+}
+
+impl<'de, 'a, 'js> de::SeqAccess<'de> for Seq<'a, 'js> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.index >= self.len {
+            return Ok(None);
+        }
+        let value = self.object.get(self.index as u32)?;
+        self.index += 1;
+        seed.deserialize(Deserializer::new(self.converter, value))
+            .map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len - self.index)
+    }
+}
+
+impl<'a, 'js> Map<'a, 'js> {
+    fn new(converter: &'a mut Converter<'js>, entries: Vec<(String, js::Value<'js>)>) -> Self {
+        Self {
+            converter,
+            entries: entries.into_iter(),
+            value: None,
+        }
+    }
+
+    fn visit<'de, V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(&mut self)
+    }
+}
+
+impl<'de, 'a, 'js> de::MapAccess<'de> for Map<'a, 'js> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                let key: StringDeserializer<Error> = key.into_deserializer();
+                seed.deserialize(key).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(Deserializer::new(self.converter, value)),
+            None => Err(de::Error::custom("value is deserialized before its key")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+impl<'de, 'a, 'js> de::EnumAccess<'de> for Enum<'a, 'js> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Error> {
+        let variant: StringDeserializer<Error> = self.variant.clone().into_deserializer();
+        Ok((seed.deserialize(variant)?, self))
+    }
+}
+
+impl<'de, 'a, 'js> de::VariantAccess<'de> for Enum<'a, 'js> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(Deserializer::new(self.converter, self.value))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(Deserializer::new(self.converter, self.value), visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(Deserializer::new(self.converter, self.value), visitor)
+    }
+}
@@ -0,0 +1,157 @@
+mod de;
+mod ser;
+
+use std::fmt;
+
+use crate::JsonOptions;
+use js::Ctx;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Serialize the value directly into a javascript value, without the intermediate
+/// [`JsonValue`](crate::JsonValue). The options apply like in
+/// [`JsonValue::into_js_with`](crate::JsonValue::into_js_with).
+pub fn to_js<'js, T>(
+    ctx: Ctx<'js>,
+    value: &T,
+    options: &JsonOptions,
+) -> Result<js::Value<'js>, js::Error>
+where
+    T: Serialize + ?Sized,
+{
+    value
+        .serialize(ser::Serializer::new(ctx, options))
+        .map_err(|e| e.0)
+}
+
+/// Deserialize the javascript value directly, without the intermediate
+/// [`JsonValue`](crate::JsonValue). The options apply like in
+/// [`JsonValue::from_js_with`](crate::JsonValue::from_js_with), including the protection
+/// against circular, too deep or too large values.
+pub fn from_js<'js, T>(
+    ctx: Ctx<'js>,
+    value: js::Value<'js>,
+    options: &JsonOptions,
+) -> Result<T, js::Error>
+where
+    T: DeserializeOwned,
+{
+    let mut converter = crate::value::Converter::new(ctx, options);
+    T::deserialize(de::Deserializer::new(&mut converter, value)).map_err(|e| e.0)
+}
+
+/// The error of the serializer and deserializer, which is a [`js::Error`] in the end.
+#[derive(Debug)]
+struct Error(js::Error);
+
+impl From<js::Error> for Error {
+    fn from(e: js::Error) -> Self {
+        Self(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(js::Error::new_into_js_message(
+            "value",
+            "value",
+            msg.to_string(),
+        ))
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(js::Error::new_from_js_message(
+            "value",
+            "value",
+            msg.to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JsonValue;
+    use js::{Context, Runtime};
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect { w: u32, h: u32 },
+    }
+
+    #[test]
+    fn values_should_round_trip_through_javascript() {
+        let rt = Runtime::new().expect("runtime");
+        let ctx = Context::full(&rt).expect("context");
+        ctx.with(|ctx| {
+            let options = JsonOptions::lossless();
+            let shapes = vec![Shape::Point, Shape::Circle(1.5), Shape::Rect { w: 2, h: 3 }];
+            let value = to_js(ctx, &shapes, &options).expect("valid");
+            let json = JsonValue::from_js_with(ctx, value.clone(), &options).expect("valid");
+            assert_eq!(
+                json.0,
+                serde_json::json!(["Point", { "Circle": 1.5 }, { "Rect": { "w": 2, "h": 3 } }])
+            );
+            let ret: Vec<Shape> = from_js(ctx, value, &options).expect("valid");
+            assert_eq!(ret, shapes);
+
+            let map: BTreeMap<String, Option<i128>> =
+                [("a".into(), Some(i128::MAX)), ("b".into(), None)].into();
+            let value = to_js(ctx, &map, &options).expect("valid");
+            let ret: BTreeMap<String, Option<i128>> = from_js(ctx, value, &options).expect("valid");
+            assert_eq!(ret, map);
+        });
+    }
+
+    #[test]
+    fn javascript_values_should_be_deserialized() {
+        let rt = Runtime::new().expect("runtime");
+        let ctx = Context::full(&rt).expect("context");
+        ctx.with(|ctx| {
+            let eval = |code: &str| ctx.eval::<js::Value, _>(code).expect("valid");
+            let options = JsonOptions::new();
+
+            // integers beyond i32 are floats in quickjs
+            let v: u64 = from_js(ctx, eval("2 ** 40"), &options).expect("valid");
+            assert_eq!(v, 1 << 40);
+            let v: (i8, bool, String) =
+                from_js(ctx, eval("[-1, true, 'x']"), &options).expect("valid");
+            assert_eq!(v, (-1, true, "x".to_owned()));
+            assert!(from_js::<(i8, i8)>(ctx, eval("[1, 2, 3]"), &options).is_err());
+
+            let circular = "const a = { b: 1 }; a.self = a; a";
+            assert!(from_js::<serde_json::Value>(ctx, eval(circular), &options).is_err());
+            let deep = "let d = []; for (let i = 0; i < 10; i++) d = [d]; d";
+            let shallow = JsonOptions::new().max_depth(5);
+            assert!(from_js::<serde_json::Value>(ctx, eval(deep), &shallow).is_err());
+
+            // enums are checked as well, otherwise a cycle would overflow the stack
+            #[derive(Debug, Deserialize)]
+            #[allow(dead_code)]
+            enum Tree {
+                Node(Box<Tree>),
+                Leaf,
+            }
+            let tree = "(() => { const t = {}; t.Node = t; return t; })()";
+            assert!(from_js::<Tree>(ctx, eval(tree), &options).is_err());
+            let marker = JsonOptions::new().circular_marker(true);
+            assert!(from_js::<Tree>(ctx, eval(tree), &marker).is_err());
+            let deep = "(() => { let n = 'Leaf'; for (let i = 0; i < 10; i++) n = { Node: n }; return n; })()";
+            assert!(from_js::<Tree>(ctx, eval(deep), &options).is_ok());
+            assert!(from_js::<Tree>(ctx, eval(deep), &shallow).is_err());
+        });
+    }
+}
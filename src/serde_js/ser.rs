@@ -0,0 +1,388 @@
+use js::{Array, Coerced, Ctx, FromJs, IntoJs, Null, Object, Type};
+use serde::{ser, Serialize};
+
+use super::Error;
+use crate::{value::int_into_js, JsonOptions};
+
+/// Serializes rust values into javascript values, like `serde_json::to_value` then
+/// `JsonValue::into_js_with`.
+#[derive(Clone, Copy)]
+pub(super) struct Serializer<'js> {
+    ctx: Ctx<'js>,
+    options: JsonOptions,
+}
+
+/// Serializes sequences and tuples into an array.
+pub(super) struct SeqSerializer<'js> {
+    ser: Serializer<'js>,
+    array: Array<'js>,
+    /// the variant to wrap the array in, for tuple variants
+    variant: Option<&'static str>,
+}
+
+/// Serializes maps and structs into an object.
+pub(super) struct MapSerializer<'js> {
+    ser: Serializer<'js>,
+    object: Object<'js>,
+    key: Option<String>,
+    /// the variant to wrap the object in, for struct variants
+    variant: Option<&'static str>,
+}
+
+impl<'js> Serializer<'js> {
+    pub(super) fn new(ctx: Ctx<'js>, options: &JsonOptions) -> Self {
+        Self {
+            ctx,
+            options: *options,
+        }
+    }
+
+    fn int(self, v: i128) -> Result<js::Value<'js>, Error> {
+        Ok(int_into_js(self.ctx, v, &self.options)?)
+    }
+
+    /// `{ variant: value }`, the externally tagged representation of serde_json
+    fn variant(self, variant: &str, value: js::Value<'js>) -> Result<js::Value<'js>, Error> {
+        let object = Object::new(self.ctx)?;
+        object.set(variant, value)?;
+        Ok(object.into_value())
+    }
+
+    fn seq(self, variant: Option<&'static str>) -> Result<SeqSerializer<'js>, Error> {
+        Ok(SeqSerializer {
+            ser: self,
+            array: Array::new(self.ctx)?,
+            variant,
+        })
+    }
+}
+
+impl<'js> ser::Serializer for Serializer<'js> {
+    type Ok = js::Value<'js>;
+    type Error = Error;
+    type SerializeSeq = SeqSerializer<'js>;
+    type SerializeTuple = SeqSerializer<'js>;
+    type SerializeTupleStruct = SeqSerializer<'js>;
+    type SerializeTupleVariant = SeqSerializer<'js>;
+    type SerializeMap = MapSerializer<'js>;
+    type SerializeStruct = MapSerializer<'js>;
+    type SerializeStructVariant = MapSerializer<'js>;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Error> {
+        Ok(js::Value::new_bool(self.ctx, v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Error> {
+        self.int(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Error> {
+        self.int(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Error> {
+        self.int(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Error> {
+        self.int(v.into())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok, Error> {
+        self.int(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Error> {
+        self.int(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Error> {
+        self.int(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Error> {
+        self.int(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Error> {
+        self.int(v.into())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok, Error> {
+        match i128::try_from(v) {
+            Ok(v) => self.int(v),
+            Err(_) => Err(ser::Error::custom("u128 is out of range")),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Error> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Error> {
+        Ok(js::Value::new_float(self.ctx, v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Error> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Error> {
+        Ok(js::String::from_str(self.ctx, v)?.into_value())
+    }
+
+    /// an array of numbers, like serde_json
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Error> {
+        let array = Array::new(self.ctx)?;
+        for (i, b) in v.iter().enumerate() {
+            array.set(i, *b)?;
+        }
+        Ok(array.into_value())
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Error> {
+        Ok(Null.into_js(self.ctx)?)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Error> {
+        let value = value.serialize(self)?;
+        self.variant(variant, value)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        self.seq(None)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        self.seq(None)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        self.seq(None)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        self.seq(Some(variant))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Ok(MapSerializer {
+            ser: self,
+            object: Object::new(self.ctx)?,
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        let mut map = self.serialize_map(Some(len))?;
+        map.variant = Some(variant);
+        Ok(map)
+    }
+}
+
+impl<'js> SeqSerializer<'js> {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let value = value.serialize(self.ser)?;
+        Ok(self.array.set(self.array.len(), value)?)
+    }
+
+    fn finish(self) -> Result<js::Value<'js>, Error> {
+        let array = self.array.into_value();
+        match self.variant {
+            Some(variant) => self.ser.variant(variant, array),
+            None => Ok(array),
+        }
+    }
+}
+
+impl<'js> ser::SerializeSeq for SeqSerializer<'js> {
+    type Ok = js::Value<'js>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl<'js> ser::SerializeTuple for SeqSerializer<'js> {
+    type Ok = js::Value<'js>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl<'js> ser::SerializeTupleStruct for SeqSerializer<'js> {
+    type Ok = js::Value<'js>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl<'js> ser::SerializeTupleVariant for SeqSerializer<'js> {
+    type Ok = js::Value<'js>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl<'js> MapSerializer<'js> {
+    fn set<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), Error> {
+        let value = value.serialize(self.ser)?;
+        Ok(self.object.set(key, value)?)
+    }
+
+    fn finish(self) -> Result<js::Value<'js>, Error> {
+        let object = self.object.into_value();
+        match self.variant {
+            Some(variant) => self.ser.variant(variant, object),
+            None => Ok(object),
+        }
+    }
+}
+
+impl<'js> ser::SerializeMap for MapSerializer<'js> {
+    type Ok = js::Value<'js>;
+    type Error = Error;
+
+    /// the keys must be strings, numbers or booleans, like serde_json
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        let key = key.serialize(self.ser)?;
+        let key = match key.type_of() {
+            // `Unknown` is a `BigInt`, e.g. a large u64 key
+            Type::String | Type::Int | Type::Float | Type::Bool | Type::Unknown => {
+                Coerced::<String>::from_js(self.ser.ctx, key)?.0
+            }
+            _ => return Err(ser::Error::custom("key must be a string")),
+        };
+        self.key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| <Error as ser::Error>::custom("value is serialized before its key"))?;
+        self.set(&key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl<'js> ser::SerializeStruct for MapSerializer<'js> {
+    type Ok = js::Value<'js>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.set(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl<'js> ser::SerializeStructVariant for MapSerializer<'js> {
+    type Ok = js::Value<'js>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.set(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
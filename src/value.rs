@@ -1,13 +1,297 @@
 use std::fmt;
 
-use crate::JsonValue;
+use crate::{BinaryEncoding, JsonOptions, JsonValue};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use itertools::Itertools;
-use js::{Array, Ctx, FromAtom, FromJs, IntoJs, Null, Object};
+use js::{Array, ArrayBuffer, Ctx, FromAtom, FromJs, Function, IntoJs, Null, Object, This, Type};
 use serde::Serialize;
 use serde_json::{json, Value};
 
+/// integers beyond ±(2^53 - 1) lose precision as javascript numbers
+pub(crate) const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+/// what a circular reference is converted into if `circular_marker` is enabled
+pub(crate) const CIRCULAR: &str = "[Circular]";
+const DEFAULT_MAX_DEPTH: usize = 128;
+const DEFAULT_MAX_SIZE: usize = 1_000_000;
+
 impl<'js> FromJs<'js> for JsonValue {
-    fn from_js(_ctx: Ctx<'js>, val: js::Value<'js>) -> Result<Self, js::Error> {
+    fn from_js(ctx: Ctx<'js>, val: js::Value<'js>) -> Result<Self, js::Error> {
+        Self::from_js_with(ctx, val, &JsonOptions::default())
+    }
+}
+
+impl<'js> IntoJs<'js> for JsonValue {
+    fn into_js(self, ctx: Ctx<'js>) -> Result<js::Value<'js>, js::Error> {
+        self.into_js_with(ctx, &JsonOptions::default())
+    }
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        Self {
+            big_int: false,
+            dates: false,
+            collections: false,
+            binary: BinaryEncoding::default(),
+            strict: false,
+            circular_marker: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+}
+
+impl JsonOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Convert `BigInt`, `Date`, `Map`, `Set` and binary data (as base64), and the integers
+    /// beyond ±2^53 into `BigInt`.
+    pub fn lossless() -> Self {
+        Self::new()
+            .big_int(true)
+            .dates(true)
+            .collections(true)
+            .binary(BinaryEncoding::Base64)
+    }
+
+    /// Convert the integers beyond ±(2^53 - 1) into `BigInt` instead of imprecise numbers, and
+    /// `BigInt` into integers, or into decimal strings if they do not fit into 64 bits.
+    /// Otherwise `BigInt` becomes `null`.
+    pub fn big_int(mut self, enabled: bool) -> Self {
+        self.big_int = enabled;
+        self
+    }
+
+    /// Convert `Date` into ISO 8601 strings (`null` if invalid). Otherwise it becomes `{}`.
+    pub fn dates(mut self, enabled: bool) -> Self {
+        self.dates = enabled;
+        self
+    }
+
+    /// Convert `Map` into objects, with the keys converted into strings, and `Set` into
+    /// arrays. Otherwise they become `{}`.
+    pub fn collections(mut self, enabled: bool) -> Self {
+        self.collections = enabled;
+        self
+    }
+
+    /// How typed arrays, `DataView` and `ArrayBuffer` are converted.
+    pub fn binary(mut self, encoding: BinaryEncoding) -> Self {
+        self.binary = encoding;
+        self
+    }
+
+    /// Fail the conversion instead of producing `null` or `{}` for the values which could not
+    /// be converted, e.g. functions, symbols, `NaN` or a `Date` when `dates` is disabled.
+    /// `undefined` is still converted into `null`.
+    pub fn strict(mut self, enabled: bool) -> Self {
+        self.strict = enabled;
+        self
+    }
+
+    /// Convert the circular references into `"[Circular]"` instead of failing the conversion.
+    pub fn circular_marker(mut self, enabled: bool) -> Self {
+        self.circular_marker = enabled;
+        self
+    }
+
+    /// Max nesting of the arrays and objects, 128 by default. Deeper values fail the
+    /// conversion.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Max number of values, counting every element and property, 1,000,000 by default.
+    /// Larger values fail the conversion, e.g. arrays which contain the same array repeatedly.
+    pub fn max_size(mut self, size: usize) -> Self {
+        self.max_size = size;
+        self
+    }
+}
+
+impl JsonValue {
+    /// Convert the javascript value into json according to the options.
+    pub fn from_js_with<'js>(
+        ctx: Ctx<'js>,
+        val: js::Value<'js>,
+        options: &JsonOptions,
+    ) -> Result<Self, js::Error> {
+        Converter::new(ctx, options).convert(val).map(Self)
+    }
+
+    /// Convert the json into a javascript value according to the options.
+    pub fn into_js_with<'js>(
+        self,
+        ctx: Ctx<'js>,
+        options: &JsonOptions,
+    ) -> Result<js::Value<'js>, js::Error> {
+        match self.0 {
+            Value::Null => Null.into_js(ctx),
+            Value::Bool(v) => Ok(js::Value::new_bool(ctx, v)),
+            Value::Number(num) => {
+                if let Some(v) = num.as_f64().filter(|_| num.is_f64()) {
+                    return Ok(js::Value::new_float(ctx, v));
+                }
+                match (num.as_i64(), num.as_u64()) {
+                    (Some(v), _) => int_into_js(ctx, v.into(), options),
+                    (None, Some(v)) => int_into_js(ctx, v.into(), options),
+                    _ => Ok(js::Value::new_float(ctx, num.as_f64().unwrap_or(f64::NAN))),
+                }
+            }
+            Value::String(v) => js::String::from_str(ctx, &v)?.into_js(ctx),
+            Value::Array(v) => {
+                let x = Array::new(ctx)?;
+                for (i, v) in v.into_iter().enumerate() {
+                    x.set(i, JsonValue(v).into_js_with(ctx, options)?)?;
+                }
+                x.into_js(ctx)
+            }
+            Value::Object(v) => {
+                let x = Object::new(ctx)?;
+                for (k, v) in v.into_iter() {
+                    x.set(k, JsonValue(v).into_js_with(ctx, options)?)?;
+                }
+                x.into_js(ctx)
+            }
+        }
+    }
+}
+
+/// Convert the integer into a javascript number, or a `BigInt` if it loses precision and
+/// `big_int` is enabled.
+pub(crate) fn int_into_js<'js>(
+    ctx: Ctx<'js>,
+    v: i128,
+    options: &JsonOptions,
+) -> Result<js::Value<'js>, js::Error> {
+    if v.unsigned_abs() <= u128::from(MAX_SAFE_INTEGER) {
+        return Ok(js::Value::new_number(ctx, v as _));
+    }
+    if options.big_int {
+        let big_int: Function = ctx.globals().get("BigInt")?;
+        return big_int.call((v.to_string(),));
+    }
+    if options.strict {
+        let message = "the integer loses precision";
+        return Err(js::Error::new_into_js_message("number", "BigInt", message));
+    }
+    Ok(js::Value::new_number(ctx, v as _))
+}
+
+/// Converts javascript values into json, see [`JsonValue::from_js_with`]. It also keeps track
+/// of the depth, size and circular references for the serde deserializer.
+pub(crate) struct Converter<'js> {
+    ctx: Ctx<'js>,
+    options: JsonOptions,
+    /// the builtin classes, looked up on first use
+    classes: Option<Classes<'js>>,
+    /// the objects being converted, from the root to the current one
+    ancestors: Vec<js::Value<'js>>,
+    /// number of values converted so far
+    size: usize,
+}
+
+pub(crate) struct Classes<'js> {
+    date: Function<'js>,
+    map: Function<'js>,
+    set: Function<'js>,
+    pub(crate) array_from: Function<'js>,
+    is_view: Function<'js>,
+    string: Function<'js>,
+}
+
+/// The builtin objects converted according to the options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Builtin {
+    Date,
+    Map,
+    Set,
+    View,
+    ArrayBuffer,
+}
+
+impl<'js> Converter<'js> {
+    pub(crate) fn new(ctx: Ctx<'js>, options: &JsonOptions) -> Self {
+        Self {
+            ctx,
+            options: *options,
+            classes: None,
+            ancestors: Vec::new(),
+            size: 0,
+        }
+    }
+
+    pub(crate) fn options(&self) -> &JsonOptions {
+        &self.options
+    }
+
+    pub(crate) fn convert(&mut self, val: js::Value<'js>) -> Result<Value, js::Error> {
+        if !self.enter(&val)? {
+            return Ok(Value::String(CIRCULAR.to_owned()));
+        }
+        let v = self.convert_value(val.clone());
+        self.leave(&val);
+        v
+    }
+
+    /// Count the value, and track it until [`Converter::leave`] if it is an object. Returns
+    /// false if it is a circular reference to be replaced by the `"[Circular]"` marker.
+    pub(crate) fn enter(&mut self, val: &js::Value<'js>) -> Result<bool, js::Error> {
+        let options = self.options;
+        self.size += 1;
+        if self.size > options.max_size {
+            return Err(self.too_large());
+        }
+        if !val.is_object() {
+            return Ok(true);
+        }
+        // shared objects are fine as long as they do not contain themselves
+        if self.ancestors.contains(val) {
+            if options.circular_marker {
+                return Ok(false);
+            }
+            return Err(unsupported("object", "json", "circular reference"));
+        }
+        if self.ancestors.len() >= options.max_depth {
+            let message = format!("the value is nested deeper than {}", options.max_depth);
+            return Err(unsupported("object", "json", &message));
+        }
+        self.ancestors.push(val.clone());
+        Ok(true)
+    }
+
+    pub(crate) fn leave(&mut self, val: &js::Value<'js>) {
+        if val.is_object() {
+            self.ancestors.pop();
+        }
+    }
+
+    /// Check the number of elements of a value before they are allocated, as each of them
+    /// counts toward the size, e.g. for the sparse `new Array(2 ** 30)`.
+    pub(crate) fn check_len(&self, len: f64) -> Result<usize, js::Error> {
+        let remaining = self.options.max_size.saturating_sub(self.size);
+        match len <= remaining as f64 {
+            true => Ok(len as usize),
+            false => Err(self.too_large()),
+        }
+    }
+
+    /// the length of the array, checked by [`Converter::check_len`]
+    pub(crate) fn array_len(&self, array: &Array<'js>) -> Result<usize, js::Error> {
+        // read as a float, `Array::len` asserts that it is an int, which fails from 2^31 on
+        let len: f64 = array.as_object().get("length")?;
+        self.check_len(len)
+    }
+
+    fn too_large(&self) -> js::Error {
+        let message = format!("the value has more than {} elements", self.options.max_size);
+        unsupported("object", "json", &message)
+    }
+
+    fn convert_value(&mut self, val: js::Value<'js>) -> Result<Value, js::Error> {
         let v = match val {
             val if val.type_name() == "null" => Value::Null,
             val if val.type_name() == "undefined" => Value::Null,
@@ -19,14 +303,19 @@ impl<'js> FromJs<'js> for JsonValue {
                 }
             }
             val if val.is_int() => val.as_int().expect("checked int").into(),
-            val if val.is_float() => val.as_float().expect("checked float").into(),
+            val if val.is_float() => {
+                let v = val.as_float().expect("checked float");
+                if !v.is_finite() && self.options.strict {
+                    return Err(unsupported("number", "json", "the number is not finite"));
+                }
+                v.into()
+            }
             val if val.is_array() => {
                 let v = val.as_array().expect("checked array");
-                let mut x = Vec::with_capacity(v.len());
-                for i in v.iter() {
-                    let v = i?;
-                    let v = JsonValue::from_js(_ctx, v)?;
-                    x.push(v.into());
+                let len = self.array_len(v)?;
+                let mut x = Vec::with_capacity(len);
+                for i in 0..len {
+                    x.push(self.convert(v.get(i)?)?);
                 }
                 Value::Array(x)
             }
@@ -36,66 +325,199 @@ impl<'js> FromJs<'js> for JsonValue {
 
                 // Check to see if this object is a function. We don't support it
                 if v.as_function().is_some() {
-                    return Ok(Self::null());
+                    if self.options.strict {
+                        return Err(unsupported(
+                            "function",
+                            "json",
+                            "functions are not supported",
+                        ));
+                    }
+                    return Ok(Value::Null);
+                }
+                if let Some(builtin) = self.builtin(&v)? {
+                    if let Some(v) = self.convert_builtin(builtin, v.clone())? {
+                        return Ok(v);
+                    }
+                    if self.options.strict {
+                        let message = "the object is not enabled in the options";
+                        return Err(unsupported("object", "json", message));
+                    }
                 }
                 // This object is a normal object
                 let mut x = json!({});
                 for i in v.props() {
                     let (k, v) = i?;
                     let k = String::from_atom(k)?;
-                    let v = JsonValue::from_js(_ctx, v)?;
-                    x[k] = v.into();
+                    x[k] = self.convert(v)?;
                 }
                 x
             }
+            val if self.options.big_int && val.type_of() == Type::Unknown => {
+                let s = self.big_int_string(val)?;
+                match (s.parse::<i64>(), s.parse::<u64>()) {
+                    (Ok(v), _) => v.into(),
+                    (_, Ok(v)) => v.into(),
+                    _ => Value::String(s),
+                }
+            }
+            val if self.options.strict => {
+                let message = "the value is not supported";
+                return Err(unsupported(val.type_name(), "json", message));
+            }
             _ => Value::Null,
         };
-        Ok(v.into())
+        Ok(v)
     }
-}
 
-impl<'js> IntoJs<'js> for JsonValue {
-    fn into_js(self, ctx: Ctx<'js>) -> Result<js::Value<'js>, js::Error> {
-        match self.0 {
-            Value::Null => Null.into_js(ctx),
-            Value::Bool(v) => Ok(js::Value::new_bool(ctx, v)),
-            Value::Number(num) => {
-                if num.is_f64() {
-                    Ok(js::Value::new_float(
-                        ctx,
-                        num.as_f64().expect("checked f64"),
-                    ))
-                } else if num.is_i64() {
-                    Ok(js::Value::new_number(
-                        ctx,
-                        num.as_i64().expect("checked f64") as _,
-                    ))
-                } else {
-                    Ok(js::Value::new_number(
-                        ctx,
-                        num.as_u64().expect("checked f64") as _,
-                    ))
+    /// the builtin the object is an instance of, if it might be converted specially
+    pub(crate) fn builtin(&mut self, obj: &Object<'js>) -> Result<Option<Builtin>, js::Error> {
+        let options = self.options;
+        let check_binary = options.strict || options.binary != BinaryEncoding::Object;
+        let check_others = options.strict || options.dates || options.collections;
+        if !check_binary && !check_others {
+            return Ok(None);
+        }
+        if check_binary {
+            if ArrayBuffer::from_object(obj.clone()).is_ok() {
+                return Ok(Some(Builtin::ArrayBuffer));
+            }
+            if self.classes()?.is_view.call((obj.clone(),))? {
+                return Ok(Some(Builtin::View));
+            }
+        }
+        if check_others {
+            let classes = self.classes()?;
+            if obj.is_instance_of(&classes.date) {
+                return Ok(Some(Builtin::Date));
+            }
+            if obj.is_instance_of(&classes.map) {
+                return Ok(Some(Builtin::Map));
+            }
+            if obj.is_instance_of(&classes.set) {
+                return Ok(Some(Builtin::Set));
+            }
+        }
+        Ok(None)
+    }
+
+    /// convert the builtin object, or return `None` if it is not enabled in the options
+    fn convert_builtin(
+        &mut self,
+        builtin: Builtin,
+        obj: Object<'js>,
+    ) -> Result<Option<Value>, js::Error> {
+        let options = self.options;
+        let v = match builtin {
+            Builtin::Date if options.dates => match self.date_string(obj)? {
+                Some(iso) => Value::String(iso),
+                None => Value::Null,
+            },
+            Builtin::Map if options.collections => {
+                self.check_len(obj.get("size")?)?;
+                let entries: Array = self.classes()?.array_from.call((obj,))?;
+                let mut x = serde_json::Map::with_capacity(self.array_len(&entries)?);
+                for entry in entries.iter::<Array>() {
+                    let entry = entry?;
+                    let key = self.map_key(entry.get(0)?)?;
+                    x.insert(key, self.convert(entry.get(1)?)?);
                 }
+                Value::Object(x)
             }
-            Value::String(v) => js::String::from_str(ctx, &v)?.into_js(ctx),
-            Value::Array(v) => {
-                let x = Array::new(ctx)?;
-                for (i, v) in v.into_iter().enumerate() {
-                    x.set(i, JsonValue(v).into_js(ctx)?)?;
+            Builtin::Set if options.collections => {
+                self.check_len(obj.get("size")?)?;
+                let values: Array = self.classes()?.array_from.call((obj,))?;
+                let mut x = Vec::with_capacity(self.array_len(&values)?);
+                for v in values.iter() {
+                    x.push(self.convert(v?)?);
                 }
-                x.into_js(ctx)
+                Value::Array(x)
             }
-            Value::Object(v) => {
-                let x = Object::new(ctx)?;
-                for (k, v) in v.into_iter() {
-                    x.set(k, JsonValue(v).into_js(ctx)?)?;
+            Builtin::View | Builtin::ArrayBuffer => match options.binary {
+                BinaryEncoding::Object => return Ok(None),
+                BinaryEncoding::Base64 => Value::String(STANDARD.encode(bytes(builtin, obj)?)),
+                // the elements of typed arrays, e.g. numbers or `BigInt`
+                BinaryEncoding::Array if obj.contains_key("length")? => {
+                    let len = self.check_len(obj.get("length")?)?;
+                    let mut x = Vec::with_capacity(len);
+                    for i in 0..len {
+                        x.push(self.convert(obj.get(i as u32)?)?);
+                    }
+                    Value::Array(x)
                 }
-                x.into_js(ctx)
+                BinaryEncoding::Array => bytes(builtin, obj)?.into(),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(v))
+    }
+
+    /// The ISO 8601 string of the `Date`, or `None` if it is invalid. Invalid dates fail the
+    /// conversion in the strict mode.
+    pub(crate) fn date_string(&self, obj: Object<'js>) -> Result<Option<String>, js::Error> {
+        let time: f64 = obj
+            .get::<_, Function>("getTime")?
+            .call((This(obj.clone()),))?;
+        if time.is_nan() {
+            if self.options.strict {
+                return Err(unsupported("Date", "json", "the date is invalid"));
             }
+            return Ok(None);
+        }
+        let iso: String = obj.get::<_, Function>("toISOString")?.call((This(obj),))?;
+        Ok(Some(iso))
+    }
+
+    /// the key of a `Map` entry as string, e.g. `"1"` for the number 1
+    pub(crate) fn map_key(&mut self, key: js::Value<'js>) -> Result<String, js::Error> {
+        match self.convert(key)? {
+            Value::String(key) => Ok(key),
+            key => Ok(key.to_string()),
+        }
+    }
+
+    /// the decimal string of a `BigInt`, which is unknown to rquickjs
+    pub(crate) fn big_int_string(&mut self, val: js::Value<'js>) -> Result<String, js::Error> {
+        self.classes()?.string.call((val,))
+    }
+
+    pub(crate) fn classes(&mut self) -> Result<&Classes<'js>, js::Error> {
+        if self.classes.is_none() {
+            let globals = self.ctx.globals();
+            let array: Object = globals.get("Array")?;
+            let array_buffer: Object = globals.get("ArrayBuffer")?;
+            self.classes = Some(Classes {
+                date: globals.get("Date")?,
+                map: globals.get("Map")?,
+                set: globals.get("Set")?,
+                array_from: array.get("from")?,
+                is_view: array_buffer.get("isView")?,
+                string: globals.get("String")?,
+            });
         }
+        Ok(self.classes.as_ref().expect("initialized classes"))
     }
 }
 
+/// the bytes of an `ArrayBuffer`, or the bytes viewed by a typed array or `DataView`
+pub(crate) fn bytes<'js>(builtin: Builtin, obj: Object<'js>) -> Result<Vec<u8>, js::Error> {
+    if let Builtin::ArrayBuffer = builtin {
+        let buffer = ArrayBuffer::from_object(obj)?;
+        return Ok(AsRef::<[u8]>::as_ref(&buffer).to_vec());
+    }
+    let buffer: ArrayBuffer = obj.get("buffer")?;
+    let offset: usize = obj.get("byteOffset")?;
+    let len: usize = obj.get("byteLength")?;
+    let bytes: &[u8] = buffer.as_ref();
+    match bytes.get(offset..offset + len) {
+        Some(bytes) => Ok(bytes.to_vec()),
+        None => Err(unsupported("object", "json", "the view is out of bounds")),
+    }
+}
+
+pub(crate) fn unsupported(from: &'static str, to: &'static str, message: &str) -> js::Error {
+    js::Error::new_from_js_message(from, to, message)
+}
+
 impl From<Value> for JsonValue {
     fn from(v: Value) -> Self {
         Self(v)
@@ -151,11 +573,16 @@ impl fmt::Display for JsonValue {
 #[cfg(test)]
 mod tests {
     use super::*;
-    #[cfg(feature = "console")]
-    use crate::builtins::con::Console;
     use crate::JsEngine;
     use anyhow::Result;
-    use js::Function;
+
+    #[js::bind(object, public)]
+    #[quickjs(bare)]
+    #[allow(non_upper_case_globals)]
+    mod obj {
+        #[derive(Debug, Clone)]
+        pub struct NativeObject;
+    }
 
     #[js::bind(object, public)]
     #[quickjs(bare, rename = "print")]
@@ -193,18 +620,182 @@ mod tests {
         let engine = JsEngine::create()?;
         let _ret: Result<()> = engine.context.with(|ctx| {
             let obj = Object::new(ctx)?;
+            ctx.globals().init_def::<Obj>()?;
             obj.set("name", "John")?;
-            #[cfg(feature = "console")]
-            obj.set("obj", Console)?;
+            obj.set("obj", obj::NativeObject)?;
             obj.set("fun", Function::new(ctx, print))?;
             let js = obj.into_js(ctx)?;
             let v = JsonValue::from_js(ctx, js)?;
-            #[cfg(feature = "console")]
             assert_eq!(v.0, json!({ "name": "John", "obj": {}, "fun": null }));
-            #[cfg(not(feature = "console"))]
-            assert_eq!(v.0, json!({ "name": "John", "fun": null }));
             Ok(())
         });
         Ok(())
     }
+
+    #[tokio::test]
+    async fn json_value_should_be_converted_losslessly() -> Result<()> {
+        let engine = JsEngine::builder()
+            .json_options(JsonOptions::lossless())
+            .build()?;
+        let code = r#"
+            const big = 2n ** 64n + 1n;
+            return {
+                id: req.id,
+                next: req.id + 1n,
+                small: 42n,
+                big,
+                date: new Date(Date.UTC(2023, 0, 2, 3, 4, 5, 6)),
+                invalid: new Date(NaN),
+                set: new Set([1, 'a', 1]),
+                map: new Map([['a', 1], [2, new Set([true])]]),
+                bytes: new Uint8Array([104, 105]),
+                view: new Uint8Array([0, 104, 105, 0]).subarray(1, 3),
+                buffer: new Uint16Array([1]).buffer,
+                missing: undefined,
+            };
+        "#;
+        let ret = engine
+            .run(code, JsonValue(json!({ "id": 9007199254740993u64 })))
+            .await?;
+        assert_eq!(
+            ret.0,
+            json!({
+                "id": 9007199254740993u64,
+                "next": 9007199254740994u64,
+                "small": 42,
+                "big": "18446744073709551617",
+                "date": "2023-01-02T03:04:05.006Z",
+                "invalid": null,
+                "set": [1, "a"],
+                "map": { "a": 1, "2": [true] },
+                "bytes": "aGk=",
+                "view": "aGk=",
+                "buffer": "AQA=",
+                "missing": null,
+            })
+        );
+
+        let options = JsonOptions::new()
+            .big_int(true)
+            .binary(BinaryEncoding::Array);
+        let engine = JsEngine::builder().json_options(options).build()?;
+        let code = r#"
+            return [new Int16Array([-1, 2]), new BigInt64Array([3n]), new Uint8Array([7]).buffer];
+        "#;
+        let ret = engine.run(code, JsonValue::null()).await?;
+        assert_eq!(ret.0, json!([[-1, 2], [3], [7]]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn json_value_should_be_converted_as_before_by_default() -> Result<()> {
+        let engine = JsEngine::builder().build()?;
+        let code = r#"
+            return {
+                id: req.id,
+                big: 1n,
+                date: new Date(0),
+                map: new Map([['a', 1]]),
+                bytes: new Uint8Array([1]),
+                nan: NaN,
+            };
+        "#;
+        let ret = engine
+            .run(code, JsonValue(json!({ "id": 9007199254740993u64 })))
+            .await?;
+        assert_eq!(
+            ret.0,
+            json!({
+                "id": 9007199254740992.0,
+                "big": null,
+                "date": {},
+                "map": {},
+                "bytes": { "0": 1 },
+                "nan": null,
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn strict_json_options_should_reject_unsupported_values() -> Result<()> {
+        let options = JsonOptions::new().dates(true).strict(true);
+        let engine = JsEngine::builder().json_options(options).build()?;
+        let ret = engine.run("return new Date(0);", JsonValue::null()).await?;
+        assert_eq!(ret.0, json!("1970-01-01T00:00:00.000Z"));
+        assert_eq!(
+            engine.run("return;", JsonValue::null()).await?.0,
+            json!(null)
+        );
+
+        for code in [
+            "return { f() {} };",
+            "return [NaN];",
+            "return Symbol('x');",
+            "return 1n;",
+            "return new Map();",
+            "return new Uint8Array(1);",
+            "return new Date(NaN);",
+        ] {
+            let ret = engine.run(code, JsonValue::null()).await;
+            assert!(ret.is_err(), "{} should fail", code);
+        }
+
+        // integers beyond 2^53 could not be passed to the script without BigInt
+        let ret = engine
+            .run("return req;", JsonValue(json!(9007199254740993u64)))
+            .await;
+        assert!(ret.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn circular_and_huge_values_should_fail_the_conversion() -> Result<()> {
+        let engine = JsEngine::builder().build()?;
+        let code = "const a = { name: 'a' }; a.self = a; return a;";
+        let ret = engine.run(code, JsonValue::null()).await;
+        assert!(matches!(ret, Err(e) if e.to_string().contains("circular reference")));
+
+        // shared objects are not circular
+        let code = "const a = [1]; return { x: a, y: [a, a] };";
+        let ret = engine.run(code, JsonValue::null()).await?;
+        assert_eq!(ret.0, json!({ "x": [1], "y": [[1], [1]] }));
+
+        let code = "let a = []; for (let i = 0; i < 1000; i++) { a = [a]; } return a;";
+        let ret = engine.run(code, JsonValue::null()).await;
+        assert!(matches!(ret, Err(e) if e.to_string().contains("nested deeper than 128")));
+
+        let code = "let a = [1]; for (let i = 0; i < 30; i++) { a = [a, a]; } return a;";
+        let ret = engine.run(code, JsonValue::null()).await;
+        assert!(matches!(ret, Err(e) if e.to_string().contains("more than 1000000 elements")));
+
+        let options = JsonOptions::new()
+            .circular_marker(true)
+            .max_depth(3)
+            .max_size(5);
+        let engine = JsEngine::builder().json_options(options).build()?;
+        let code = "const a = { b: [] }; a.b.push(a); return a;";
+        let ret = engine.run(code, JsonValue::null()).await?;
+        assert_eq!(ret.0, json!({ "b": ["[Circular]"] }));
+        let ret = engine.run("return [[[[]]]];", JsonValue::null()).await;
+        assert!(ret.is_err());
+        let ret = engine
+            .run("return [1, 2, 3, 4, 5];", JsonValue::null())
+            .await;
+        assert!(ret.is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "console")]
+    #[tokio::test]
+    async fn circular_values_should_be_logged() -> Result<()> {
+        let engine = JsEngine::builder().build()?;
+        let code = "const a = { n: 1 }; a.self = a; console.log(a); return 1;";
+        let output = engine.run_captured(code, JsonValue::null()).await?;
+        assert_eq!(
+            output.logs[0].args,
+            vec![JsonValue(json!({ "n": 1, "self": "[Circular]" }))]
+        );
+        Ok(())
+    }
 }